@@ -1,4 +1,5 @@
 use crate::db::Activity;
+use crate::evaluator::all_activity_types;
 use serde_derive::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
@@ -11,14 +12,60 @@ pub enum AchievementRank {
     Diamond,
 }
 
+// Points awarded per unlocked achievement of each rank
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AchievementPoints {
+    pub bronze: f64,
+    pub silver: f64,
+    pub gold: f64,
+    pub diamond: f64,
+}
+
+impl Default for AchievementPoints {
+    fn default() -> Self {
+        Self {
+            bronze: 1.0,
+            silver: 2.0,
+            gold: 4.0,
+            diamond: 8.0,
+        }
+    }
+}
+
+impl AchievementPoints {
+    pub fn points(&self, rank: AchievementRank) -> f64 {
+        match rank {
+            AchievementRank::Bronze => self.bronze,
+            AchievementRank::Silver => self.silver,
+            AchievementRank::Gold => self.gold,
+            AchievementRank::Diamond => self.diamond,
+        }
+    }
+
+    // Points for all the given unlocked achievements together
+    pub fn total(&self, ranks: impl IntoIterator<Item = AchievementRank>) -> f64 {
+        ranks.into_iter().map(|rank| self.points(rank)).sum()
+    }
+}
+
 #[derive(Debug)]
 pub enum AchievementType {
     UnlockType(usize),
     Streak(i32, Activity),
+    // Streak over any activity
+    AnyStreak(i32),
     Distance(f64, Activity),
     ActivityCount(i32, Activity),
-    AtDate(i32),
+    // Month and day of month
+    AtDate(u32, u32),
     FullCalender(),
+    // Every one of the criteria
+    All(Vec<AchievementType>),
+    // At least one of the criteria
+    Any(Vec<AchievementType>),
+    // The criterion is met by the activities of some span of this many consecutive days
+    Within(i32, Box<AchievementType>),
 }
 
 #[derive(Debug)]
@@ -27,273 +74,385 @@ pub struct AchievementData {
     pub description: String,
     pub rank: AchievementRank,
     pub achievement_type: AchievementType,
+    // Title and description are not shown until unlocked
+    pub secret: bool,
+}
+
+impl AchievementData {
+    fn new(
+        title: &str,
+        description: &str,
+        rank: AchievementRank,
+        achievement_type: AchievementType,
+    ) -> Self {
+        Self {
+            title: title.to_string(),
+            description: description.to_string(),
+            rank,
+            achievement_type,
+            secret: false,
+        }
+    }
+
+    fn secret(self) -> Self {
+        Self {
+            secret: true,
+            ..self
+        }
+    }
 }
 
 pub fn get_achievements() -> Vec<AchievementData> {
     vec![
-        AchievementData {
-            title: "Game on".to_string(),
-            description: "Register one activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::UnlockType(1),
-        },
-        AchievementData {
-            title: "Alternative training".to_string(),
-            description: "Register two different activity types".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::UnlockType(2),
-        },
-        AchievementData {
-            title: "Multisport master".to_string(),
-            description: "Register all different activity types".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::UnlockType(4),
-        },
-        AchievementData {
-            title: "Walk of life".to_string(),
-            description: "Register one walk activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::ActivityCount(1, Activity::WALK),
-        },
-        AchievementData {
-            title: "Keep on walking".to_string(),
-            description: "Register three walk activities".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::ActivityCount(3, Activity::WALK),
-        },
-        AchievementData {
-            title: "Walk this way".to_string(),
-            description: "Register six walk activities".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::ActivityCount(6, Activity::WALK),
-        },
-        AchievementData {
-            title: "Moon walker".to_string(),
-            description: "Register ten walk activities".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::ActivityCount(10, Activity::WALK),
-        },
-        AchievementData {
-            title: "Run forrest run".to_string(),
-            description: "Register one run activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::ActivityCount(1, Activity::RUN),
-        },
-        AchievementData {
-            title: "Keep on running".to_string(),
-            description: "Register three run activities".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::ActivityCount(3, Activity::RUN),
-        },
-        AchievementData {
-            title: "Run to the hills".to_string(),
-            description: "Register six run activities".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::ActivityCount(6, Activity::RUN),
-        },
-        AchievementData {
-            title: "No one can stop you".to_string(),
-            description: "Register ten run activities".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::ActivityCount(10, Activity::RUN),
-        },
-        AchievementData {
-            title: "I want to ride my bicycle".to_string(),
-            description: "Register one bike activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::ActivityCount(1, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Saddle sore".to_string(),
-            description: "Register three bike activities".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::ActivityCount(3, Activity::BIKE),
-        },
-        AchievementData {
-            title: "It's leg day".to_string(),
-            description: "Register six bike activities".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::ActivityCount(6, Activity::BIKE),
-        },
-        AchievementData {
-            title: "The pain cave is my home".to_string(),
-            description: "Register ten bike activities".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::ActivityCount(10, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Let it snow".to_string(),
-            description: "Register one ski activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::ActivityCount(1, Activity::SKI),
-        },
-        AchievementData {
-            title: "Double pole is the shit".to_string(),
-            description: "Register three ski activities".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::ActivityCount(3, Activity::SKI),
-        },
-        AchievementData {
-            title: "Need more wax".to_string(),
-            description: "Register six ski activities".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::ActivityCount(6, Activity::SKI),
-        },
-        AchievementData {
-            title: "Swix blue extra for breakfast".to_string(),
-            description: "Register ten ski activities".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::ActivityCount(10, Activity::SKI),
-        },
-        AchievementData {
-            title: "Who needs climbing shoes".to_string(),
-            description: "Register one climbing activity".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::ActivityCount(1, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "Gecko slab master!".to_string(),
-            description: "Register three climbing activities".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::ActivityCount(3, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "Blood, pain and injuries".to_string(),
-            description: "Register six climbing activities".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::ActivityCount(6, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "I'm Spiderman!".to_string(),
-            description: "Register ten climbing activities".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::ActivityCount(10, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "Half marathon".to_string(),
-            description: "Register 21k running".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Distance(21.0, Activity::RUN),
-        },
-        AchievementData {
-            title: "Marathon".to_string(),
-            description: "Register 42k running".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Distance(42.0, Activity::RUN),
-        },
-        AchievementData {
-            title: "Century ride".to_string(),
-            description: "Register 100k cycle".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Distance(100.0, Activity::BIKE),
-        },
-        AchievementData {
-            title: "VR315".to_string(),
-            description: "Register 315k cycle".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Distance(315.0, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Vasaloppet".to_string(),
-            description: "Register 90k skiing".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Distance(90.0, Activity::SKI),
-        },
-        AchievementData {
-            title: "Wall time is quality time".to_string(),
-            description: "Register 10h of climbing".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Distance(600.0, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "There is only the wall".to_string(),
-            description: "Register 20h of climbing".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Distance(1200.0, Activity::CLIMB),
-        },
-        AchievementData {
-            title: "Ho Ho Ho".to_string(),
-            description: "Register an activity on Christmas eve".to_string(),
-            rank: AchievementRank::Bronze,
-            achievement_type: AchievementType::AtDate(23),
-        },
-        AchievementData {
-            title: "Active every day".to_string(),
-            description: "Register an activity every day".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::FullCalender(),
-        },
-        AchievementData {
-            title: "Run x3".to_string(),
-            description: "Three running days in a row".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Streak(3, Activity::RUN),
-        },
-        AchievementData {
-            title: "Run x5".to_string(),
-            description: "Five running days in a row".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Streak(5, Activity::RUN),
-        },
-        AchievementData {
-            title: "Run x7".to_string(),
-            description: "Seven running days in a row".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Streak(7, Activity::RUN),
-        },
-        AchievementData {
-            title: "Bike x3".to_string(),
-            description: "Three biking days in a row".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Streak(3, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Bike x5".to_string(),
-            description: "Five biking days in a row".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Streak(5, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Bike x7".to_string(),
-            description: "Seven biking days in a row".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Streak(7, Activity::BIKE),
-        },
-        AchievementData {
-            title: "Walk x3".to_string(),
-            description: "Three walking days in a row".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Streak(3, Activity::WALK),
-        },
-        AchievementData {
-            title: "Walk x5".to_string(),
-            description: "Five walking days in a row".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Streak(5, Activity::WALK),
-        },
-        AchievementData {
-            title: "Walk x7".to_string(),
-            description: "Seven walking days in a row".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Streak(7, Activity::WALK),
-        },
-        AchievementData {
-            title: "Ski x3".to_string(),
-            description: "Three skiing days in a row".to_string(),
-            rank: AchievementRank::Silver,
-            achievement_type: AchievementType::Streak(3, Activity::SKI),
-        },
-        AchievementData {
-            title: "Ski x5".to_string(),
-            description: "Five skiing days in a row".to_string(),
-            rank: AchievementRank::Gold,
-            achievement_type: AchievementType::Streak(5, Activity::SKI),
-        },
-        AchievementData {
-            title: "Ski x7".to_string(),
-            description: "Seven skiing days in a row".to_string(),
-            rank: AchievementRank::Diamond,
-            achievement_type: AchievementType::Streak(7, Activity::SKI),
-        },
+        AchievementData::new(
+            "Game on",
+            "Register one activity",
+            AchievementRank::Bronze,
+            AchievementType::UnlockType(1),
+        ),
+        AchievementData::new(
+            "Alternative training",
+            "Register two different activity types",
+            AchievementRank::Silver,
+            AchievementType::UnlockType(2),
+        ),
+        AchievementData::new(
+            "Multisport master",
+            "Register all different activity types",
+            AchievementRank::Gold,
+            AchievementType::UnlockType(all_activity_types()),
+        ),
+        AchievementData::new(
+            "Walk of life",
+            "Register one walk activity",
+            AchievementRank::Bronze,
+            AchievementType::ActivityCount(1, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Keep on walking",
+            "Register three walk activities",
+            AchievementRank::Silver,
+            AchievementType::ActivityCount(3, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Walk this way",
+            "Register six walk activities",
+            AchievementRank::Gold,
+            AchievementType::ActivityCount(6, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Moon walker",
+            "Register ten walk activities",
+            AchievementRank::Diamond,
+            AchievementType::ActivityCount(10, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Run forrest run",
+            "Register one run activity",
+            AchievementRank::Bronze,
+            AchievementType::ActivityCount(1, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Keep on running",
+            "Register three run activities",
+            AchievementRank::Silver,
+            AchievementType::ActivityCount(3, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Run to the hills",
+            "Register six run activities",
+            AchievementRank::Gold,
+            AchievementType::ActivityCount(6, Activity::RUN),
+        ),
+        AchievementData::new(
+            "No one can stop you",
+            "Register ten run activities",
+            AchievementRank::Diamond,
+            AchievementType::ActivityCount(10, Activity::RUN),
+        ),
+        AchievementData::new(
+            "I want to ride my bicycle",
+            "Register one bike activity",
+            AchievementRank::Bronze,
+            AchievementType::ActivityCount(1, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Saddle sore",
+            "Register three bike activities",
+            AchievementRank::Silver,
+            AchievementType::ActivityCount(3, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "It's leg day",
+            "Register six bike activities",
+            AchievementRank::Gold,
+            AchievementType::ActivityCount(6, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "The pain cave is my home",
+            "Register ten bike activities",
+            AchievementRank::Diamond,
+            AchievementType::ActivityCount(10, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Let it snow",
+            "Register one ski activity",
+            AchievementRank::Bronze,
+            AchievementType::ActivityCount(1, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Double pole is the shit",
+            "Register three ski activities",
+            AchievementRank::Silver,
+            AchievementType::ActivityCount(3, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Need more wax",
+            "Register six ski activities",
+            AchievementRank::Gold,
+            AchievementType::ActivityCount(6, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Swix blue extra for breakfast",
+            "Register ten ski activities",
+            AchievementRank::Diamond,
+            AchievementType::ActivityCount(10, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Who needs climbing shoes",
+            "Register one climbing activity",
+            AchievementRank::Bronze,
+            AchievementType::ActivityCount(1, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "Gecko slab master!",
+            "Register three climbing activities",
+            AchievementRank::Silver,
+            AchievementType::ActivityCount(3, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "Blood, pain and injuries",
+            "Register six climbing activities",
+            AchievementRank::Gold,
+            AchievementType::ActivityCount(6, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "I'm Spiderman!",
+            "Register ten climbing activities",
+            AchievementRank::Diamond,
+            AchievementType::ActivityCount(10, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "Half marathon",
+            "Register 21k running",
+            AchievementRank::Silver,
+            AchievementType::Distance(21.0, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Marathon",
+            "Register 42k running",
+            AchievementRank::Gold,
+            AchievementType::Distance(42.0, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Century ride",
+            "Register 100k cycle",
+            AchievementRank::Silver,
+            AchievementType::Distance(100.0, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "VR315",
+            "Register 315k cycle",
+            AchievementRank::Diamond,
+            AchievementType::Distance(315.0, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Vasaloppet",
+            "Register 90k skiing",
+            AchievementRank::Diamond,
+            AchievementType::Distance(90.0, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Wall time is quality time",
+            "Register 10h of climbing",
+            AchievementRank::Silver,
+            AchievementType::Distance(600.0, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "There is only the wall",
+            "Register 20h of climbing",
+            AchievementRank::Gold,
+            AchievementType::Distance(1200.0, Activity::CLIMB),
+        ),
+        AchievementData::new(
+            "Ho Ho Ho",
+            "Register an activity on Christmas eve",
+            AchievementRank::Bronze,
+            AchievementType::AtDate(12, 24),
+        ),
+        AchievementData::new(
+            "Active every day",
+            "Register an activity every day",
+            AchievementRank::Diamond,
+            AchievementType::FullCalender(),
+        ),
+        AchievementData::new(
+            "Run x3",
+            "Three running days in a row",
+            AchievementRank::Silver,
+            AchievementType::Streak(3, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Run x5",
+            "Five running days in a row",
+            AchievementRank::Gold,
+            AchievementType::Streak(5, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Run x7",
+            "Seven running days in a row",
+            AchievementRank::Diamond,
+            AchievementType::Streak(7, Activity::RUN),
+        ),
+        AchievementData::new(
+            "Bike x3",
+            "Three biking days in a row",
+            AchievementRank::Silver,
+            AchievementType::Streak(3, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Bike x5",
+            "Five biking days in a row",
+            AchievementRank::Gold,
+            AchievementType::Streak(5, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Bike x7",
+            "Seven biking days in a row",
+            AchievementRank::Diamond,
+            AchievementType::Streak(7, Activity::BIKE),
+        ),
+        AchievementData::new(
+            "Walk x3",
+            "Three walking days in a row",
+            AchievementRank::Silver,
+            AchievementType::Streak(3, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Walk x5",
+            "Five walking days in a row",
+            AchievementRank::Gold,
+            AchievementType::Streak(5, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Walk x7",
+            "Seven walking days in a row",
+            AchievementRank::Diamond,
+            AchievementType::Streak(7, Activity::WALK),
+        ),
+        AchievementData::new(
+            "Ski x3",
+            "Three skiing days in a row",
+            AchievementRank::Silver,
+            AchievementType::Streak(3, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Ski x5",
+            "Five skiing days in a row",
+            AchievementRank::Gold,
+            AchievementType::Streak(5, Activity::SKI),
+        ),
+        AchievementData::new(
+            "Ski x7",
+            "Seven skiing days in a row",
+            AchievementRank::Diamond,
+            AchievementType::Streak(7, Activity::SKI),
+        ),
+        AchievementData::new(
+            "On a roll",
+            "Five active days in a row",
+            AchievementRank::Silver,
+            AchievementType::AnyStreak(5),
+        ),
+        AchievementData::new(
+            "Creature of habit",
+            "Ten active days in a row",
+            AchievementRank::Gold,
+            AchievementType::AnyStreak(10),
+        ),
+        AchievementData::new(
+            "Unstoppable",
+            "Twenty active days in a row",
+            AchievementRank::Diamond,
+            AchievementType::AnyStreak(20),
+        ),
+        AchievementData::new(
+            "Brick workout",
+            "Run and bike within the same week",
+            AchievementRank::Silver,
+            AchievementType::Within(
+                7,
+                Box::new(AchievementType::All(vec![
+                    AchievementType::ActivityCount(1, Activity::RUN),
+                    AchievementType::ActivityCount(1, Activity::BIKE),
+                ])),
+            ),
+        ),
+        AchievementData::new(
+            "Pentathlon",
+            "All five activity types in five days in a row",
+            AchievementRank::Diamond,
+            AchievementType::Within(
+                5,
+                Box::new(AchievementType::UnlockType(all_activity_types())),
+            ),
+        )
+        .secret(),
+        AchievementData::new(
+            "Cold feet",
+            "Ski or climb on Christmas eve",
+            AchievementRank::Silver,
+            AchievementType::Within(
+                1,
+                Box::new(AchievementType::All(vec![
+                    AchievementType::AtDate(12, 24),
+                    AchievementType::Any(vec![
+                        AchievementType::ActivityCount(1, Activity::SKI),
+                        AchievementType::ActivityCount(1, Activity::CLIMB),
+                    ]),
+                ])),
+            ),
+        )
+        .secret(),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AchievementRank::*;
+
+    #[test]
+    fn achievement_points() {
+        let points = AchievementPoints::default();
+        assert_eq!(points.points(Bronze), 1.0);
+        assert_eq!(points.points(Diamond), 8.0);
+        assert_eq!(points.total([]), 0.0);
+        assert_eq!(points.total([Bronze, Bronze, Silver, Gold, Diamond]), 16.0);
+
+        let points: AchievementPoints = serde_json::from_str(r#"{"gold": 10}"#).unwrap();
+        assert_eq!(points.points(Gold), 10.0);
+        assert_eq!(points.points(Silver), 2.0);
+        assert_eq!(points.total([Gold, Silver]), 12.0);
+    }
+
+    #[test]
+    fn secret_achievements() {
+        let secret: Vec<String> = get_achievements()
+            .into_iter()
+            .filter(|a| a.secret)
+            .map(|a| a.title)
+            .collect();
+        assert_eq!(secret, vec!["Pentathlon", "Cold feet"]);
+    }
+}
@@ -0,0 +1,207 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use chrono::Utc;
+use hyper::HeaderMap;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::config;
+use crate::db::Db;
+
+// Sent by clients older than the Authorization header support
+pub const LEGACY_HEADER: &str = "Authentification";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AuthConfig {
+    // When set, logins hand out tokens signed with this secret instead of session keys, and
+    // requests are authenticated without looking up the session
+    pub token_secret: Option<String>,
+    // Signed tokens are only revoked for routes that check the session, see `authenticate`, so
+    // they expire after this many hours
+    pub token_hours: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            token_secret: None,
+            token_hours: 12,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+    // Fingerprint of the session key the token was issued for, which is replaced when the
+    // password changes
+    #[serde(default)]
+    sid: String,
+}
+
+// A signed token holds no secret, so it only holds a hash of the session key
+fn session_id(session_key: &str) -> String {
+    let hash = Sha256::digest(session_key.as_bytes());
+    hash[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Token of `Authorization: Bearer <token>`, or of the legacy header
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    if let Some(value) = headers.get(hyper::header::AUTHORIZATION) {
+        let value = value.to_str().ok()?;
+        let (scheme, token) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") || token.trim().is_empty() {
+            return None;
+        }
+        return Some(token.trim().to_string());
+    }
+    headers
+        .get(LEGACY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|token| token.to_string())
+}
+
+pub fn sign(
+    user: Uuid,
+    session_key: &str,
+    secret: &str,
+    issued_at: i64,
+    hours: i64,
+) -> Result<String> {
+    let claims = Claims {
+        sub: user.to_string(),
+        iat: issued_at,
+        exp: issued_at + hours * 3600,
+        sid: session_id(session_key),
+    };
+    Ok(encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?)
+}
+
+fn verify(token: &str, secret: &str) -> Result<Claims> {
+    let data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )?;
+    Ok(data.claims)
+}
+
+// What a client should send to authenticate after logging in with `session_key`
+pub fn issue_token(db: &Db, session_key: String) -> Result<String> {
+    let auth = &config().auth;
+    match &auth.token_secret {
+        Some(secret) => {
+            let user = db.get_user_from_session(session_key.clone())?;
+            sign(
+                user,
+                &session_key,
+                secret,
+                Utc::now().timestamp(),
+                auth.token_hours,
+            )
+        }
+        None => Ok(session_key),
+    }
+}
+
+// User of the token, None if it is invalid. Signed tokens are checked without the database
+// unless `current_session` is set, which makes sure they were issued for the user's current
+// session and so stop working once the password is changed or reset.
+pub fn authenticate(db: &Db, token: &str, current_session: bool) -> Result<Option<Uuid>> {
+    match &config().auth.token_secret {
+        // Session keys are uuids, signed tokens have three dot separated parts
+        Some(secret) if token.contains('.') => {
+            let Ok(claims) = verify(token, secret) else {
+                return Ok(None);
+            };
+            let Ok(user) = Uuid::from_str(&claims.sub) else {
+                return Ok(None);
+            };
+            if current_session && !is_current(db, user, &claims)? {
+                return Ok(None);
+            }
+            Ok(Some(user))
+        }
+        _ => db.session_user(token),
+    }
+}
+
+fn is_current(db: &Db, user: Uuid, claims: &Claims) -> Result<bool> {
+    Ok(db
+        .session_user_key(user)?
+        .is_some_and(|key| session_id(&key) == claims.sid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_tokens() {
+        let user = Uuid::new_v4();
+        let now = Utc::now().timestamp();
+        let token = sign(user, "key", "secret", now, 1).unwrap();
+        let claims = verify(&token, "secret").unwrap();
+        assert_eq!(claims.sub, user.to_string());
+        assert_eq!(claims.sid, session_id("key"));
+        assert_ne!(claims.sid, session_id("other key"));
+        assert!(!token.contains("key"));
+        assert!(verify(&token, "other secret").is_err());
+
+        let expired = sign(user, "key", "secret", now - 3 * 3600, 1).unwrap();
+        assert!(verify(&expired, "secret").is_err());
+
+        let mut tampered = token.clone();
+        tampered.insert(token.find('.').unwrap() + 1, 'x');
+        assert!(verify(&tampered, "secret").is_err());
+    }
+
+    #[test]
+    fn current_sessions() {
+        let db = Db::open_in_memory().unwrap();
+        db.add_user("anna", "password1", None, None).unwrap();
+        let user = db.get_user_id("anna", "password1").unwrap();
+        let key = db.get_session_key(user, true).unwrap();
+        let now = Utc::now().timestamp();
+        let claims = verify(&sign(user, &key, "secret", now, 1).unwrap(), "secret").unwrap();
+        assert!(is_current(&db, user, &claims).unwrap());
+
+        let new_key = db.change_password(user, "password1", "password2").unwrap();
+        assert!(!is_current(&db, user, &claims).unwrap());
+        assert_eq!(authenticate(&db, &key, true).unwrap(), None);
+        assert_eq!(authenticate(&db, &new_key, true).unwrap(), Some(user));
+    }
+
+    #[test]
+    fn bearer_headers() {
+        let cases = vec![
+            (vec![("authorization", "Bearer abc")], Some("abc")),
+            (vec![("authorization", "bearer  abc ")], Some("abc")),
+            (vec![("authorization", "Basic abc")], None),
+            (vec![("authorization", "Bearer ")], None),
+            (vec![("authentification", "abc")], Some("abc")),
+            (
+                vec![("authorization", "Bearer new"), ("authentification", "old")],
+                Some("new"),
+            ),
+            (vec![], None),
+        ];
+        for (headers, expected) in cases {
+            let mut map = HeaderMap::new();
+            for (name, value) in &headers {
+                map.insert(*name, value.parse().unwrap());
+            }
+            assert_eq!(bearer_token(&map).as_deref(), expected, "{:?}", headers);
+        }
+    }
+}
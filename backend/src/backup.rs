@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use serde_derive::Deserialize;
+
+use crate::db::Db;
+
+// Apart from `db-before-restore-*` and backups taken by hand, which are never removed
+const BACKUP_PREFIX: &str = "scheduled-";
+const BACKUP_SUFFIX: &str = ".sqlite";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BackupConfig {
+    // Scheduled backups are only taken when a directory is set
+    pub dir: Option<String>,
+    // At least 1
+    pub interval_hours: u64,
+    // Number of scheduled backups to keep, older ones are removed. At least 1
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+// Scheduled backups in the directory, oldest first
+fn backups_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(BACKUP_PREFIX))
+                .and_then(|name| name.strip_suffix(BACKUP_SUFFIX))
+                .is_some_and(|time| NaiveDateTime::parse_from_str(time, TIMESTAMP_FORMAT).is_ok())
+        })
+        .collect();
+    // The timestamp in the name sorts in time order
+    backups.sort();
+    Ok(backups)
+}
+
+// Takes a backup into the directory and removes the ones exceeding `keep`
+pub fn scheduled_backup(db: &Db, config: &BackupConfig, dir: &str) -> Result<PathBuf> {
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}{}{}",
+        BACKUP_PREFIX,
+        Utc::now().format(TIMESTAMP_FORMAT),
+        BACKUP_SUFFIX
+    ));
+    db.backup_to(&path)?;
+
+    let backups = backups_in(dir)?;
+    if backups.len() > config.keep {
+        for old in &backups[..backups.len() - config.keep] {
+            std::fs::remove_file(old)?;
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_scheduled() {
+        let dir = std::env::temp_dir().join(format!("backups-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let others = [
+            "db-before-restore-1669852800.sqlite",
+            "scheduled-notes.sqlite",
+            "manual.sqlite",
+        ];
+        for name in others.iter().chain(&[
+            "scheduled-20221201T000000Z.sqlite",
+            "scheduled-20221202T000000Z.sqlite",
+        ]) {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let db = Db::open_in_memory().unwrap();
+        let config = BackupConfig {
+            dir: None,
+            interval_hours: 1,
+            keep: 2,
+        };
+        let latest = scheduled_backup(&db, &config, dir.to_str().unwrap()).unwrap();
+        assert_eq!(
+            backups_in(&dir).unwrap(),
+            vec![dir.join("scheduled-20221202T000000Z.sqlite"), latest]
+        );
+        for name in others {
+            assert!(dir.join(name).exists(), "{}", name);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
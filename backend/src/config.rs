@@ -0,0 +1,130 @@
+use std::{path::Path, sync::OnceLock};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde_derive::Deserialize;
+
+use crate::achievements::AchievementPoints;
+use crate::auth::AuthConfig;
+use crate::backup::BackupConfig;
+use crate::cors::CorsConfig;
+use crate::evaluator::StreakRules;
+use crate::mail::MailConfig;
+use crate::moderation::PlausibilityConfig;
+use crate::oidc::OidcConfig;
+use crate::ratelimit::RateLimitConfig;
+use crate::scoring::{LatePolicy, RankingMode};
+use crate::tls::{redirect_location, TlsConfig};
+
+// Path of the config file can be overridden with this environment variable
+const CONFIG_ENV: &str = "ADVENTOFGAH_CONFIG";
+const CONFIG_FILE: &str = "config.json";
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    // Day 0 of the challenge
+    pub start_date: NaiveDate,
+    // Used for users that have not set a time zone of their own
+    pub default_timezone: String,
+    // Minutes after midnight during which yesterday can still be logged at full score
+    pub grace_minutes: i64,
+    // Penalty for activities logged after their day
+    pub late_policy: LatePolicy,
+    // Rest days and freezes that keep streaks going
+    pub streak_rules: StreakRules,
+    // Points per unlocked achievement, counted in the leaderboard in the combined ranking mode
+    pub achievement_points: AchievementPoints,
+    pub ranking: RankingMode,
+    pub backup: BackupConfig,
+    // How password reset mails are sent
+    pub mail: MailConfig,
+    // Minutes a password reset token can be used
+    pub reset_token_minutes: i64,
+    // Limits on login, registration and password reset attempts
+    pub rate_limit: RateLimitConfig,
+    // Limits on logged values and when activities are flagged for review
+    pub plausibility: PlausibilityConfig,
+    // Ids of the users that can moderate flagged activities, see `GET /api/me/export`
+    pub admins: Vec<String>,
+    pub auth: AuthConfig,
+    // Single sign-on, disabled unless set
+    pub oidc: Option<OidcConfig>,
+    // Larger request bodies are answered with 413 Payload Too Large
+    pub max_body_bytes: usize,
+    // Origins other than the backend's own that the frontend can be served from
+    pub cors: CorsConfig,
+    // Serves the built frontend from this directory, e.g. `../frontend/build`
+    pub frontend_dir: Option<String>,
+    // Serves HTTPS when set, see `TlsConfig`
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            start_date: NaiveDate::from_ymd_opt(2022, 12, 1).expect("Start date was invalid"),
+            default_timezone: "Europe/Stockholm".to_string(),
+            grace_minutes: 0,
+            late_policy: LatePolicy::default(),
+            streak_rules: StreakRules::default(),
+            achievement_points: AchievementPoints::default(),
+            ranking: RankingMode::default(),
+            backup: BackupConfig::default(),
+            mail: MailConfig::default(),
+            reset_token_minutes: 60,
+            rate_limit: RateLimitConfig::default(),
+            plausibility: PlausibilityConfig::default(),
+            admins: Vec::new(),
+            auth: AuthConfig::default(),
+            oidc: None,
+            max_body_bytes: 64 * 1024,
+            cors: CorsConfig::default(),
+            frontend_dir: None,
+            tls: None,
+        }
+    }
+}
+
+impl Config {
+    fn load() -> Result<Self> {
+        let path = std::env::var(CONFIG_ENV).unwrap_or_else(|_| CONFIG_FILE.to_string());
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+        let config: Self = serde_json::from_slice(&std::fs::read(&path)?)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    // Values that would otherwise only fail once they are used
+    fn check(&self) -> Result<()> {
+        if !(0..24 * 60).contains(&self.grace_minutes) {
+            return Err(anyhow::anyhow!(
+                "grace_minutes must be from 0 up to 1439, not {}",
+                self.grace_minutes
+            ));
+        }
+        self.late_policy.check()?;
+        if self.backup.interval_hours == 0 {
+            return Err(anyhow::anyhow!("backup.interval_hours must be at least 1"));
+        }
+        if self.backup.keep == 0 {
+            return Err(anyhow::anyhow!("backup.keep must be at least 1"));
+        }
+        if let Some(host) = self.tls.as_ref().and_then(|tls| tls.public_host.as_ref()) {
+            if redirect_location(host, "/", 443).is_none() {
+                return Err(anyhow::anyhow!(
+                    "tls.public_host is not a host name: {}",
+                    host
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| Config::load().expect("Config load failed"))
+}
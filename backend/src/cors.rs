@@ -0,0 +1,168 @@
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::Method;
+use serde_derive::Deserialize;
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE";
+const ALLOWED_HEADERS: &str = "Authorization, Content-Type, Authentification";
+// Response headers the frontend may read besides the simple ones
+const EXPOSED_HEADERS: &str = "Retry-After, WWW-Authenticate";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CorsConfig {
+    // Origins such as `https://gah.example.com` that may call the api from a browser, `*` allows
+    // any origin
+    pub allowed_origins: Vec<String>,
+    // Lets browsers send cookies and HTTP auth, only for origins listed by name
+    pub allow_credentials: bool,
+    // How long browsers may cache a preflight response
+    pub max_age_seconds: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            max_age_seconds: 600,
+        }
+    }
+}
+
+// A preflight is an OPTIONS request the browser sends before the real one
+pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::OPTIONS
+        && headers.contains_key(header::ORIGIN)
+        && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+impl CorsConfig {
+    fn listed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| o.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+
+    // Headers for a response to `origin`, None if the origin is not allowed
+    pub fn headers(&self, origin: &str, preflight: bool) -> Option<HeaderMap> {
+        let listed = self.listed(origin);
+        if !listed && !self.allowed_origins.iter().any(|o| o == "*") {
+            return None;
+        }
+        let credentials = listed && self.allow_credentials;
+        let mut headers = HeaderMap::new();
+        let allow_origin = if listed { origin } else { "*" };
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_str(allow_origin).ok()?,
+        );
+        // The answer depends on the origin, so caches must not share it between origins
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        if credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if preflight {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static(ALLOWED_METHODS),
+            );
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_static(ALLOWED_HEADERS),
+            );
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, self.max_age_seconds.into());
+        } else {
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSED_HEADERS),
+            );
+        }
+        Some(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let config = |origins: &[&str], credentials: bool| CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            allow_credentials: credentials,
+            max_age_seconds: 60,
+        };
+        let gah = "https://gah.example.com";
+        let other = "https://evil.example.com";
+        // Config, origin, allowed origin header, credentials header
+        let cases = vec![
+            (config(&[], false), gah, None, false),
+            (config(&[gah], false), gah, Some(gah), false),
+            (
+                config(&["https://gah.example.com/"], true),
+                gah,
+                Some(gah),
+                true,
+            ),
+            (config(&[gah], true), other, None, false),
+            (config(&["*"], false), other, Some("*"), false),
+            // Credentials are never allowed through the wildcard
+            (config(&["*"], true), other, Some("*"), false),
+            (config(&["*", gah], true), gah, Some(gah), true),
+        ];
+        for (config, origin, allow_origin, credentials) in cases {
+            let headers = config.headers(origin, false);
+            assert_eq!(
+                headers
+                    .as_ref()
+                    .and_then(|h| h.get(header::ACCESS_CONTROL_ALLOW_ORIGIN))
+                    .map(|v| v.to_str().unwrap()),
+                allow_origin,
+                "{:?} {}",
+                config,
+                origin
+            );
+            assert_eq!(
+                headers
+                    .as_ref()
+                    .is_some_and(|h| h.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)),
+                credentials,
+                "{:?} {}",
+                config,
+                origin
+            );
+        }
+    }
+
+    #[test]
+    fn preflights() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_preflight(&Method::OPTIONS, &headers));
+        headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://a.example"),
+        );
+        assert!(!is_preflight(&Method::OPTIONS, &headers));
+        headers.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("PUT"),
+        );
+        assert!(is_preflight(&Method::OPTIONS, &headers));
+        assert!(!is_preflight(&Method::GET, &headers));
+
+        let config = CorsConfig {
+            allowed_origins: vec!["https://a.example".to_string()],
+            ..CorsConfig::default()
+        };
+        let preflight = config.headers("https://a.example", true).unwrap();
+        assert_eq!(
+            preflight[header::ACCESS_CONTROL_ALLOW_METHODS],
+            ALLOWED_METHODS
+        );
+        assert_eq!(preflight[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(!preflight.contains_key(header::ACCESS_CONTROL_EXPOSE_HEADERS));
+    }
+}
@@ -1,23 +1,41 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, path::Path, str::FromStr};
 
 use anyhow::Result;
-use chrono::NaiveDate;
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
-use rusqlite::Connection;
+use rusqlite::{Connection, DatabaseName, OpenFlags, OptionalExtension};
 use serde_derive::{Deserialize, Serialize};
 use serde_rusqlite::*;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 use uuid::Uuid;
 
-use crate::achievements::{get_achievements, AchievementRank, AchievementType};
+use crate::achievements::{get_achievements, AchievementRank};
+use crate::config::config;
+use crate::doors::{door_contents, BonusRule, Door, DoorContent};
+use crate::evaluator::{progress, ActivityEntry, ActivityStats, Challenge, Streaks};
+use crate::export::{
+    Export, ExportAchievement, ExportActivity, ExportStanding, ExportUser, PersonalData,
+    EXPORT_SCHEMA_VERSION,
+};
+use crate::history::{leaderboard_history, LeaderboardHistory, Snapshot};
+use crate::mail::parse_address;
+use crate::moderation::{self, flag_reason, Decision, FlaggedActivity, ModerationStatus};
+use crate::oidc::{username_candidate, IdClaims};
+use crate::recompute::{ranks, standings, RecomputeReport, ScoreChange};
+use crate::scoring::{activity_score, multiplier, LatePolicy};
+use crate::stats::{user_stats, ScoredEntry, UserStats};
+use crate::validation::{self, ClientError, ValidationError, Validator};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct User {
     id: String,
     username: String,
     pass: String,
+    timezone: String,
+    email: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -26,10 +44,18 @@ pub struct Session {
     key: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PointsBreakdown {
+    activities: f64,
+    achievements: f64,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct LeaderboardDetail {
     username: String,
+    // Total the leaderboard is ranked by, see `breakdown` for where it came from
     points: f64,
+    breakdown: PointsBreakdown,
     bike_dst: f64,
     walk_dst: f64,
     run_dst: f64,
@@ -39,6 +65,39 @@ pub struct LeaderboardDetail {
     silver_achievements: i32,
     gold_achievements: i32,
     diamond_achievements: i32,
+    current_streak: i32,
+    longest_streak: i32,
+}
+
+// Limits the leaderboard to one activity type and/or a range of days. Only scores and distances
+// are filtered, achievements and streaks are always for the whole challenge.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct LeaderboardFilter {
+    pub activity: Option<Activity>,
+    // First and last day, both included
+    pub from: Option<i32>,
+    pub to: Option<i32>,
+}
+
+impl LeaderboardFilter {
+    pub fn check(&self) -> Result<()> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(ValidationError::field("to", "Must not be before from"));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.activity.is_none() && self.from.is_none() && self.to.is_none()
+    }
+
+    fn matches(&self, record: &ActivityRecord) -> bool {
+        self.activity.is_none_or(|a| a == record.activity)
+            && self.from.is_none_or(|from| record.event_id >= from)
+            && self.to.is_none_or(|to| record.event_id <= to)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -48,6 +107,7 @@ pub struct LeaderBoardInfo {
     details: Vec<LeaderboardDetail>,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, EnumIter, Hash, Eq)]
 pub enum Activity {
     BIKE = 0,
@@ -67,6 +127,8 @@ pub struct ActivityInfo {
 pub struct LoggedActivityInfo {
     day: i32,
     info: ActivityInfo,
+    #[serde(default)]
+    status: ModerationStatus,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -75,6 +137,15 @@ pub struct Event {
     distance: i32,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DoorRecord {
+    id: i32,
+    target: String,
+    message: String,
+    image_url: Option<String>,
+    bonus: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct ActivityRecord {
     user: String,
@@ -82,6 +153,10 @@ pub struct ActivityRecord {
     activity: Activity,
     score: f64,
     distance: f64,
+    days_late: i32,
+    late_policy: String,
+    status: ModerationStatus,
+    flag_reason: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -97,39 +172,103 @@ pub struct Achievement {
     description: String,
     unlocked: bool,
     rank: AchievementRank,
+    progress: f64,
+    target: f64,
+    secret: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ScoreVersion {
+    id: i64,
+    created: String,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ScoreHistoryInfo {
+    version: i64,
+    created: String,
+    reason: String,
+    points: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SnapshotRecord {
+    day: i32,
+    user: String,
+    points: f64,
+    rank: i64,
 }
 
 pub struct Db {
     conn: Connection,
 }
 
-fn today_unsafe() -> i32 {
-    let start = NaiveDate::from_ymd_opt(2022, 12, 1)
-        .expect("Todays date was invalid")
-        .and_hms_opt(0, 0, 0)
-        .expect("Invalid HMS for date");
-    chrono::offset::Local::now()
-        .naive_local()
-        .signed_duration_since(start)
-        .num_days() as i32
+const DB_FILE: &str = "db";
+
+// Stored in the user_version pragma, bump when the tables change
+pub const SCHEMA_VERSION: i32 = 4;
+
+pub const CHALLENGE_DAYS: i32 = 24;
+
+pub fn challenge() -> Challenge {
+    Challenge {
+        start_date: config().start_date,
+        days: CHALLENGE_DAYS,
+        streak_rules: config().streak_rules.clone(),
+    }
+}
+
+fn local_time(tz: Tz, now: DateTime<Utc>) -> NaiveDateTime {
+    now.with_timezone(&tz).naive_local()
+}
+
+fn now_in(tz: Tz) -> NaiveDateTime {
+    local_time(tz, Utc::now())
 }
 
-pub fn today() -> i32 {
-    today_unsafe().max(0).min(23) as i32
+fn day_at(time: NaiveDateTime) -> i32 {
+    (time.date() - config().start_date).num_days() as i32
 }
 
-fn not_yet_started() -> bool {
-    today_unsafe() < 0
+fn today_unsafe(tz: Tz) -> i32 {
+    day_at(now_in(tz))
 }
 
-fn multiplier(act: Activity) -> i32 {
-    match act {
-        Activity::BIKE => 3,
-        Activity::RUN => 1,
-        Activity::SKI => 2,
-        Activity::WALK => 1,
-        Activity::CLIMB => 20,
+fn today_at(time: NaiveDateTime) -> i32 {
+    day_at(time).clamp(0, CHALLENGE_DAYS - 1)
+}
+
+pub fn today(tz: Tz) -> i32 {
+    today_at(now_in(tz))
+}
+
+fn not_yet_started(tz: Tz) -> bool {
+    today_unsafe(tz) < 0
+}
+
+// Number of days `now` is after `day`, where the first `grace_minutes` after midnight yesterday
+// still counts as today
+fn days_late_at(now: NaiveDateTime, day: i32, grace_minutes: i64) -> i32 {
+    let late = day_at(now) - day;
+    if late == 1 && now.time() < NaiveTime::MIN + Duration::minutes(grace_minutes) {
+        return 0;
     }
+    late.max(0)
+}
+
+fn days_late(tz: Tz, day: i32) -> i32 {
+    days_late_at(now_in(tz), day, config().grace_minutes)
+}
+
+// Time zone of the challenge itself, used for things that happen for everyone at once
+fn challenge_timezone() -> Result<Tz> {
+    parse_timezone(&config().default_timezone)
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz> {
+    name.parse::<Tz>()
+        .map_err(|_| anyhow::anyhow!("Unknown time zone: {}", name))
 }
 
 fn get_daily_available(dist: i32) -> Vec<ActivityInfo> {
@@ -148,11 +287,20 @@ impl Db {
         // Super ineffecient to create new conn all the time but the conn won't clone :/
         // And not expecting any super load on the server :Ps
 
-        let conn = Connection::open("db")?;
+        let conn = Connection::open(DB_FILE)?;
 
         Ok(Self { conn })
     }
 
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        let db = Self {
+            conn: Connection::open_in_memory()?,
+        };
+        db.init()?;
+        Ok(db)
+    }
+
     pub fn init(&self) -> Result<()> {
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS \"USERS\" (
@@ -163,6 +311,10 @@ impl Db {
             );",
             [],
         )?;
+        self.add_column_if_missing("USERS", "timezone", "TEXT NOT NULL DEFAULT ''")?;
+        self.add_column_if_missing("USERS", "email", "TEXT NOT NULL DEFAULT ''")?;
+        // 0 for users created by single sign-on until they set a password
+        self.add_column_if_missing("USERS", "pass_set", "INTEGER NOT NULL DEFAULT 1")?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS \"SESSION\" (
             \"id\"	TEXT NOT NULL UNIQUE,
@@ -171,6 +323,25 @@ impl Db {
         );",
             [],
         )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"OIDCIDENTITY\" (
+                \"issuer\" TEXT NOT NULL,
+                \"subject\" TEXT NOT NULL,
+                \"user\" TEXT NOT NULL,
+                PRIMARY KEY(\"issuer\", \"subject\")
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"RESETTOKEN\" (
+                \"token\" TEXT NOT NULL UNIQUE,
+                \"user\" TEXT NOT NULL,
+                \"expires\" TEXT NOT NULL,
+                \"used\" INTEGER NOT NULL,
+                PRIMARY KEY(\"token\")
+            );",
+            [],
+        )?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS \"ACTIVITYRECORD\" (
                 \"user\" TEXT NOT NULL,
@@ -181,6 +352,10 @@ impl Db {
             );",
             [],
         )?;
+        self.add_column_if_missing("ACTIVITYRECORD", "days_late", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("ACTIVITYRECORD", "late_policy", "TEXT NOT NULL DEFAULT ''")?;
+        self.add_column_if_missing("ACTIVITYRECORD", "status", "TEXT NOT NULL DEFAULT 'Ok'")?;
+        self.add_column_if_missing("ACTIVITYRECORD", "flag_reason", "TEXT NOT NULL DEFAULT ''")?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS \"EVENT\" (
                 \"id\" INTEGER NOT NULL,
@@ -189,6 +364,46 @@ impl Db {
             );",
             [],
         )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"DOOR\" (
+                \"id\" INTEGER NOT NULL,
+                \"target\" TEXT NOT NULL,
+                \"message\" TEXT NOT NULL,
+                \"image_url\" TEXT,
+                \"bonus\" TEXT,
+                PRIMARY KEY(\"id\")
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"SCOREVERSION\" (
+                \"id\" INTEGER NOT NULL,
+                \"created\" TEXT NOT NULL,
+                \"reason\" TEXT NOT NULL,
+                PRIMARY KEY(\"id\" AUTOINCREMENT)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"SCOREHISTORY\" (
+                \"version\" INTEGER NOT NULL,
+                \"user\" TEXT NOT NULL,
+                \"event_id\" INTEGER NOT NULL,
+                \"score\" REAL NOT NULL,
+                \"late_policy\" TEXT NOT NULL
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS \"LEADERBOARDSNAPSHOT\" (
+                \"day\" INTEGER NOT NULL,
+                \"user\" TEXT NOT NULL,
+                \"points\" REAL NOT NULL,
+                \"rank\" INTEGER NOT NULL,
+                PRIMARY KEY(\"day\", \"user\")
+            );",
+            [],
+        )?;
         let mut query = self.conn.prepare("SELECT * FROM EVENT").unwrap();
         let res = from_rows::<Event>(query.query([]).unwrap());
 
@@ -204,10 +419,11 @@ impl Db {
             distances.append(&mut d7);
             distances.shuffle(&mut thread_rng());
 
-            let mut day = 0;
-            for distance in distances {
-                let event = Event { id: day, distance };
-                day += 1;
+            for (day, distance) in distances.into_iter().enumerate() {
+                let event = Event {
+                    id: day as i32,
+                    distance,
+                };
                 self.conn
                     .execute(
                         "INSERT INTO EVENT (id, distance) VALUES (:id, :distance)",
@@ -217,29 +433,172 @@ impl Db {
             }
         }
 
+        self.init_doors()?;
+
+        self.conn
+            .pragma_update(None, "user_version", SCHEMA_VERSION)?;
         Ok(())
     }
 
-    pub fn add_user(&self, username: &str, pass: &str) -> Result<()> {
+    // Copies the database using SQLite's online backup, safe while the server is running
+    pub fn backup_to(&self, path: &Path) -> Result<()> {
+        self.conn.backup(DatabaseName::Main, path, None)?;
+        Ok(())
+    }
+
+    // Replaces the database with the backup, after making sure it is a database this version can
+    // migrate. The current database is backed up to `backup_of_current` first.
+    pub fn restore_from(&mut self, path: &Path, backup_of_current: &Path) -> Result<()> {
+        if !path.exists() {
+            return Err(anyhow::anyhow!("No such backup: {}", path.display()));
+        }
+        let backup = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let integrity: String = backup.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if integrity != "ok" {
+            return Err(anyhow::anyhow!("Backup is corrupt: {}", integrity));
+        }
+        let version: i32 = backup.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "Backup has schema version {}, newer than the supported {}",
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        let tables: i32 = backup.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('USERS', 'SESSION', 'ACTIVITYRECORD', 'EVENT')",
+            [],
+            |row| row.get(0),
+        )?;
+        if tables != 4 {
+            return Err(anyhow::anyhow!("Backup is not an advent of gah database"));
+        }
+        drop(backup);
+
+        self.backup_to(backup_of_current)?;
+        self.conn.restore(DatabaseName::Main, path, None::<fn(_)>)?;
+        // Older backups are brought up to date by the regular migrations
+        self.init()
+    }
+
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
         let mut query = self
             .conn
-            .prepare("SELECT * FROM USERS WHERE username = (?)")
-            .unwrap();
-        let res = from_rows::<User>(query.query([username]).unwrap());
+            .prepare(&format!("PRAGMA table_info(\"{}\")", table))?;
+        let columns = query
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<String>, _>>()?;
+        if !columns.iter().any(|c| c == column) {
+            self.conn.execute(
+                &format!(
+                    "ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}",
+                    table, column, definition
+                ),
+                [],
+            )?;
+        }
+        Ok(())
+    }
 
+    fn init_doors(&self) -> Result<()> {
+        let mut query = self.conn.prepare("SELECT * FROM DOOR").unwrap();
+        let res = from_rows::<DoorRecord>(query.query([]).unwrap());
         if res.count() > 0 {
-            return Err(anyhow::anyhow!("User alread registered"));
+            return Ok(());
+        }
+
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM EVENT ORDER BY id")
+            .unwrap();
+        let events =
+            from_rows::<Event>(query.query([]).unwrap()).collect::<Result<Vec<Event>, _>>()?;
+        let distances: Vec<i32> = events.iter().map(|e| e.distance).collect();
+
+        for (event, content) in events.iter().zip(door_contents(&distances)?) {
+            let bonus = match content.bonus {
+                Some(bonus) => Some(serde_json::to_string(&bonus)?),
+                None => None,
+            };
+            let door = DoorRecord {
+                id: event.id,
+                target: content.target,
+                message: content.message,
+                image_url: content.image_url,
+                bonus,
+            };
+            self.conn
+                .execute(
+                    "INSERT INTO DOOR (id, target, message, image_url, bonus) VALUES (:id, :target, :message, :image_url, :bonus)",
+                    to_params_named(&door).unwrap().to_slice().as_slice(),
+                )
+                .unwrap();
+        }
+        Ok(())
+    }
+
+    // Usernames are unique regardless of case, `except` is the user being renamed
+    fn username_taken(&self, username: &str, except: Option<Uuid>) -> Result<bool> {
+        let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
+        let res = from_rows::<User>(query.query([]).unwrap());
+        let lowercase = username.to_lowercase();
+        for user in res {
+            let user = user?;
+            if user.username.to_lowercase() == lowercase
+                && except.is_none_or(|except| except.to_string() != user.id)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn add_user(
+        &self,
+        username: &str,
+        pass: &str,
+        timezone: Option<&str>,
+        email: Option<&str>,
+    ) -> Result<()> {
+        let mut validator = Validator::new()
+            .check("username", validation::username(username))
+            .check("pass", validation::password(pass));
+        if let Some(tz) = timezone {
+            validator = validator.check("timezone", validation::timezone(tz));
+        }
+        if let Some(email) = email {
+            validator = validator.check("email", validation::email(email));
+        }
+        validator.finish()?;
+        if self.username_taken(username, None)? {
+            return Err(ValidationError::field("username", "Is already taken"));
+        }
+        if let Some(email) = email {
+            if self.email_taken(&parse_address(email)?, None)? {
+                return Err(ValidationError::field("email", "Is already in use"));
+            }
         }
 
         let user = User {
             id: Uuid::new_v4().to_string(),
             username: username.to_string(),
             pass: pass.to_string(),
+            // Locked like in `set_user_timezone`, so a new account can not open doors early
+            timezone: match timezone {
+                Some(tz) if not_yet_started(challenge_timezone()?) => {
+                    parse_timezone(tz)?.name().to_string()
+                }
+                _ => String::new(),
+            },
+            email: match email {
+                Some(email) => parse_address(email)?,
+                None => String::new(),
+            },
         };
 
         self.conn
             .execute(
-                "INSERT INTO USERS (id, username, pass) VALUES (:id, :username, :pass)",
+                "INSERT INTO USERS (id, username, pass, timezone, email) VALUES (:id, :username, :pass, :timezone, :email)",
                 to_params_named(&user).unwrap().to_slice().as_slice(),
             )
             .unwrap();
@@ -256,7 +615,7 @@ impl Db {
         if let Some(data) = res.next() {
             return Ok(Uuid::from_str(&data?.id)?);
         }
-        Err(anyhow::anyhow!("User pass incorrect"))
+        Err(ClientError::unauthorized("User pass incorrect"))
     }
 
     pub fn get_user_name(&self, id: &String) -> Result<String> {
@@ -268,7 +627,313 @@ impl Db {
         if let Some(data) = res.next() {
             return Ok(data?.username);
         }
-        Err(anyhow::anyhow!("Could not find user"))
+        Err(ClientError::not_found("Could not find user"))
+    }
+
+    fn get_user(&self, user: Uuid) -> Result<User> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM USERS WHERE id = (?)")
+            .unwrap();
+        let mut res = from_rows::<User>(query.query([user.to_string()]).unwrap());
+        if let Some(data) = res.next() {
+            return Ok(data?);
+        }
+        Err(ClientError::not_found("Could not find user"))
+    }
+
+    fn check_password(&self, user: Uuid, pass: &str) -> Result<()> {
+        if self.get_user(user)?.pass != pass {
+            return Err(ClientError::unauthorized("User pass incorrect"));
+        }
+        Ok(())
+    }
+
+    // Returns a new session key, the old one and thereby all other logins stop working
+    pub fn change_password(&self, user: Uuid, old_pass: &str, new_pass: &str) -> Result<String> {
+        Validator::new()
+            .check("new_pass", validation::password(new_pass))
+            .finish()?;
+        self.check_password(user, old_pass)?;
+        self.conn.execute(
+            "UPDATE USERS SET pass = (?), pass_set = 1 WHERE id = (?)",
+            [new_pass, &user.to_string()],
+        )?;
+        self.conn
+            .execute("DELETE FROM SESSION WHERE id = (?)", [user.to_string()])?;
+        self.get_session_key(user, true)
+    }
+
+    // Emails are unique regardless of case, `except` is the user changing theirs
+    fn email_taken(&self, email: &str, except: Option<Uuid>) -> Result<bool> {
+        let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
+        let res = from_rows::<User>(query.query([]).unwrap());
+        let lowercase = email.to_lowercase();
+        for user in res {
+            let user = user?;
+            if user.email.to_lowercase() == lowercase
+                && except.is_none_or(|except| except.to_string() != user.id)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn has_password(&self, user: Uuid) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT pass_set FROM USERS WHERE id = (?)",
+            [user.to_string()],
+            |row| row.get(0),
+        )?)
+    }
+
+    // User logging in through the identity provider. The identity is linked to `link`, a user
+    // that started the login from their account, or a new user is created on the first login.
+    // Emails are never used to link, nobody has confirmed owning the email of a local account.
+    pub fn oidc_user(&self, issuer: &str, claims: &IdClaims, link: Option<Uuid>) -> Result<Uuid> {
+        let linked: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT user FROM OIDCIDENTITY WHERE issuer = (?) AND subject = (?)",
+                [issuer, &claims.sub],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(user) = linked {
+            let user = Uuid::from_str(&user)?;
+            if link.is_some_and(|link| link != user) {
+                return Err(ClientError::conflict(
+                    "The identity is already linked to another user",
+                ));
+            }
+            return Ok(user);
+        }
+
+        let transaction = self.conn.unchecked_transaction()?;
+        let id = match link {
+            Some(user) => self.get_user(user)?.id,
+            None => {
+                let candidate = username_candidate(claims);
+                let mut username = candidate.clone();
+                let mut suffix = 1;
+                while self.username_taken(&username, None)? {
+                    suffix += 1;
+                    username = format!("{}{}", candidate, suffix);
+                }
+                // Kept only if verified and not in use, the owner of that account can link it
+                let email = claims
+                    .email
+                    .as_deref()
+                    .filter(|_| claims.email_verified)
+                    .and_then(|email| parse_address(email).ok())
+                    .filter(|email| !self.email_taken(email, None).unwrap_or(true));
+                let user = User {
+                    id: Uuid::new_v4().to_string(),
+                    username,
+                    // Nobody knows it, a password can be set with a password reset
+                    pass: Uuid::new_v4().to_string(),
+                    timezone: String::new(),
+                    email: email.unwrap_or_default(),
+                };
+                self.conn.execute(
+                    "INSERT INTO USERS (id, username, pass, timezone, email, pass_set) VALUES (:id, :username, :pass, :timezone, :email, 0)",
+                    to_params_named(&user).unwrap().to_slice().as_slice(),
+                )?;
+                user.id
+            }
+        };
+        self.conn.execute(
+            "INSERT INTO OIDCIDENTITY (issuer, subject, user) VALUES (?, ?, ?)",
+            [issuer, &claims.sub, &id],
+        )?;
+        transaction.commit()?;
+        Ok(Uuid::from_str(&id)?)
+    }
+
+    pub fn set_user_email(&self, user: Uuid, email: &str) -> Result<String> {
+        Validator::new()
+            .check("email", validation::email(email))
+            .finish()?;
+        let email = parse_address(email)?;
+        if self.email_taken(&email, Some(user))? {
+            return Err(ValidationError::field("email", "Is already in use"));
+        }
+        self.conn.execute(
+            "UPDATE USERS SET email = (?) WHERE id = (?)",
+            [&email, &user.to_string()],
+        )?;
+        Ok(email)
+    }
+
+    // Creates a single use token for resetting the password of the user with the given username
+    // or email. Returns the address to mail it to, None if there is no such user with an email.
+    // Emails shared by several users, from before they had to be unique, match nobody.
+    pub fn create_reset_token(&self, username_or_email: &str) -> Result<Option<(String, String)>> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM USERS WHERE email != '' AND (lower(username) = lower(?1) OR lower(email) = lower(?1))")
+            .unwrap();
+        let mut users = from_rows::<User>(query.query([username_or_email.trim()]).unwrap())
+            .collect::<Result<Vec<User>, _>>()?;
+        if users.len() != 1 {
+            return Ok(None);
+        }
+        let user = users.remove(0);
+
+        // Only the latest token is valid
+        self.conn
+            .execute("DELETE FROM RESETTOKEN WHERE user = (?)", [&user.id])?;
+        let token = Uuid::new_v4().to_string();
+        let expires = Utc::now() + Duration::minutes(config().reset_token_minutes);
+        self.conn.execute(
+            "INSERT INTO RESETTOKEN (token, user, expires, used) VALUES (?1, ?2, ?3, 0)",
+            [&token, &user.id, &expires.to_rfc3339()],
+        )?;
+        Ok(Some((user.email, token)))
+    }
+
+    // Sets a new password using a reset token and returns a new session key
+    pub fn reset_password(&self, token: &str, pass: &str) -> Result<String> {
+        Validator::new()
+            .check("pass", validation::password(pass))
+            .finish()?;
+        let invalid = || ValidationError::field("token", "Is invalid or has expired");
+        let (user, expires, used): (String, String, bool) = self
+            .conn
+            .query_row(
+                "SELECT user, expires, used FROM RESETTOKEN WHERE token = (?)",
+                [token],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .map_err(|_| invalid())?;
+        if used || DateTime::parse_from_rfc3339(&expires)? < Utc::now() {
+            return Err(invalid());
+        }
+
+        let transaction = self.conn.unchecked_transaction()?;
+        self.conn
+            .execute("UPDATE RESETTOKEN SET used = 1 WHERE token = (?)", [token])?;
+        self.conn.execute(
+            "UPDATE USERS SET pass = (?), pass_set = 1 WHERE id = (?)",
+            [pass, &user],
+        )?;
+        self.conn
+            .execute("DELETE FROM SESSION WHERE id = (?)", [&user])?;
+        transaction.commit()?;
+        self.get_session_key(Uuid::parse_str(&user)?, true)
+    }
+
+    pub fn rename_user(&self, user: Uuid, username: &str) -> Result<()> {
+        Validator::new()
+            .check("username", validation::username(username))
+            .finish()?;
+        if self.username_taken(username, Some(user))? {
+            return Err(ValidationError::field("username", "Is already taken"));
+        }
+        self.conn.execute(
+            "UPDATE USERS SET username = (?) WHERE id = (?)",
+            [username, &user.to_string()],
+        )?;
+        Ok(())
+    }
+
+    // Removes the user and everything stored about them. Users of single sign-on may not know
+    // any password, logging in with the identity provider is enough for them.
+    // Users created by single sign-on that never set a password can not give one
+    pub fn delete_user(&self, user: Uuid, pass: &str) -> Result<()> {
+        if self.has_password(user)? {
+            self.check_password(user, pass)?;
+        }
+        let id = user.to_string();
+        let transaction = self.conn.unchecked_transaction()?;
+        self.conn
+            .execute("DELETE FROM ACTIVITYRECORD WHERE user = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM SCOREHISTORY WHERE user = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM LEADERBOARDSNAPSHOT WHERE user = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM RESETTOKEN WHERE user = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM OIDCIDENTITY WHERE user = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM SESSION WHERE id = (?)", [&id])?;
+        self.conn
+            .execute("DELETE FROM USERS WHERE id = (?)", [&id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn export_user_data(&self, user: Uuid) -> Result<PersonalData> {
+        let data = self.get_user(user)?;
+        let mut activities = Vec::new();
+        for activity in self.user_activities(user)? {
+            activities.push(ExportActivity {
+                user_id: activity.user,
+                username: data.username.clone(),
+                day: activity.event_id,
+                activity: activity.activity,
+                distance: activity.distance,
+                score: activity.score,
+                days_late: activity.days_late,
+            });
+        }
+        activities.sort_by_key(|a| a.day);
+        let achievements = self
+            .get_acheivements(user)?
+            .achievements
+            .into_iter()
+            .filter(|a| a.unlocked)
+            .map(|a| ExportAchievement {
+                user_id: data.id.clone(),
+                username: data.username.clone(),
+                title: a.title,
+                rank: a.rank,
+            })
+            .collect();
+        Ok(PersonalData {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            exported_at: Utc::now().to_rfc3339(),
+            user: ExportUser {
+                id: data.id,
+                username: data.username,
+                timezone: data.timezone,
+            },
+            email: data.email,
+            activities,
+            achievements,
+            stats: self.get_user_stats(user)?,
+            score_history: self.get_score_history(user)?,
+        })
+    }
+
+    pub fn get_user_timezone(&self, user: Uuid) -> Result<Tz> {
+        let data = self.get_user(user)?;
+        if data.timezone.is_empty() {
+            return parse_timezone(&config().default_timezone);
+        }
+        parse_timezone(&data.timezone)
+    }
+
+    // Locked once the challenge has started or the user has logged anything, as the time zone
+    // decides when days open and how late activities are
+    pub fn set_user_timezone(&self, user: Uuid, timezone: &str) -> Result<()> {
+        Validator::new()
+            .check("timezone", validation::timezone(timezone))
+            .finish()?;
+        if !not_yet_started(challenge_timezone()?) || !self.user_activities(user)?.is_empty() {
+            return Err(ValidationError::field(
+                "timezone",
+                "Can not be changed once the challenge has started",
+            ));
+        }
+        let tz = parse_timezone(timezone)?;
+        self.conn.execute(
+            "UPDATE USERS SET timezone = (?) WHERE id = (?)",
+            [tz.name().to_string(), user.to_string()],
+        )?;
+        Ok(())
     }
 
     pub fn get_session_key(&self, user: Uuid, create_if_missing: bool) -> Result<String> {
@@ -276,8 +941,8 @@ impl Db {
             .conn
             .prepare("SELECT * FROM SESSION WHERE id = (?)")
             .unwrap();
-        let res = from_rows::<Session>(query.query([user.to_string()]).unwrap());
-        for session in res {
+        let mut res = from_rows::<Session>(query.query([user.to_string()]).unwrap());
+        if let Some(session) = res.next() {
             return Ok(session?.key);
         }
 
@@ -299,21 +964,41 @@ impl Db {
     }
 
     pub fn get_user_from_session(&self, key: String) -> Result<Uuid> {
-        let mut query = self
+        self.session_user(&key)?
+            .ok_or_else(|| anyhow::anyhow!("Could not find user"))
+    }
+
+    // None for keys of no session, unlike failing to look it up
+    pub fn session_user(&self, key: &str) -> Result<Option<Uuid>> {
+        let user: Option<String> = self
             .conn
-            .prepare("SELECT * FROM SESSION WHERE key = (?)")
-            .unwrap();
-        let res = from_rows::<Session>(query.query([key]).unwrap());
-        for session in res {
-            return Ok(Uuid::from_str(&session?.id)?);
-        }
-        Err(anyhow::anyhow!("Could not find user"))
+            .query_row("SELECT id FROM SESSION WHERE key = (?)", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(user.map(|user| Uuid::from_str(&user)).transpose()?)
+    }
+
+    pub fn session_user_key(&self, user: Uuid) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT key FROM SESSION WHERE id = (?)",
+                [user.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
     }
 
-    fn get_user_leaderboard_distances_and_score(&self, user: String) -> Result<LeaderboardDetail> {
+    fn get_user_leaderboard_distances_and_score(
+        &self,
+        user: String,
+        filter: &LeaderboardFilter,
+    ) -> Result<LeaderboardDetail> {
         let mut details = LeaderboardDetail {
             username: self.get_user_name(&user)?,
             points: 0.0,
+            breakdown: PointsBreakdown::default(),
             bike_dst: 0.0,
             walk_dst: 0.0,
             run_dst: 0.0,
@@ -323,6 +1008,8 @@ impl Db {
             silver_achievements: 0,
             gold_achievements: 0,
             diamond_achievements: 0,
+            current_streak: 0,
+            longest_streak: 0,
         };
 
         let mut query = self
@@ -332,6 +1019,9 @@ impl Db {
         let res = from_rows::<ActivityRecord>(query.query([user]).unwrap());
         for activity in res {
             let activity = activity?;
+            if !activity.status.counts() || !filter.matches(&activity) {
+                continue;
+            }
             match activity.activity {
                 Activity::BIKE => details.bike_dst += activity.distance,
                 Activity::RUN => details.run_dst += activity.distance,
@@ -344,30 +1034,60 @@ impl Db {
         Ok(details)
     }
 
-    fn get_user_leaderboard_entry(&self, user: String) -> Result<LeaderboardDetail> {
-        let mut entry = self.get_user_leaderboard_distances_and_score(user.clone())?;
-        let achievements = self.get_acheivements(Uuid::from_str(&user)?)?;
-        for achievement in achievements.achievements {
-            if achievement.unlocked {
-                match achievement.rank {
-                    AchievementRank::Bronze => entry.bronze_achievements += 1,
-                    AchievementRank::Silver => entry.silver_achievements += 1,
-                    AchievementRank::Gold => entry.gold_achievements += 1,
-                    AchievementRank::Diamond => entry.diamond_achievements += 1,
-                }
+    fn get_user_leaderboard_entry(
+        &self,
+        user: String,
+        filter: &LeaderboardFilter,
+    ) -> Result<LeaderboardDetail> {
+        let mut entry = self.get_user_leaderboard_distances_and_score(user.clone(), filter)?;
+        let streaks = self.get_streaks(Uuid::from_str(&user)?)?;
+        entry.current_streak = streaks.current;
+        entry.longest_streak = streaks.longest;
+        let ranks = self.unlocked_ranks(Uuid::from_str(&user)?)?;
+        for rank in &ranks {
+            match rank {
+                AchievementRank::Bronze => entry.bronze_achievements += 1,
+                AchievementRank::Silver => entry.silver_achievements += 1,
+                AchievementRank::Gold => entry.gold_achievements += 1,
+                AchievementRank::Diamond => entry.diamond_achievements += 1,
             }
         }
+        entry.breakdown.achievements = config().achievement_points.total(ranks);
+        entry.breakdown.activities = entry.points;
+        // Achievements are not tied to activity types or days, so filtered boards only count scores
+        if filter.is_empty() {
+            entry.points = config()
+                .ranking
+                .total(entry.breakdown.activities, entry.breakdown.achievements);
+        }
         Ok(entry)
     }
 
-    pub fn get_leaderboard(&self) -> Result<LeaderBoardInfo> {
+    fn unlocked_ranks(&self, user: Uuid) -> Result<Vec<AchievementRank>> {
+        let achievements = self.get_acheivements(user)?;
+        Ok(achievements
+            .achievements
+            .iter()
+            .filter(|a| a.unlocked)
+            .map(|a| a.rank)
+            .collect())
+    }
+
+    fn get_achievement_points(&self, user: Uuid) -> Result<f64> {
+        Ok(config()
+            .achievement_points
+            .total(self.unlocked_ranks(user)?))
+    }
+
+    pub fn get_leaderboard(&self, filter: &LeaderboardFilter) -> Result<LeaderBoardInfo> {
+        filter.check()?;
         let mut board: Vec<LeaderboardDetail> = Vec::new();
 
         let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
         let res = from_rows::<User>(query.query([]).unwrap());
 
         for user in res {
-            board.push(self.get_user_leaderboard_entry(user?.id)?)
+            board.push(self.get_user_leaderboard_entry(user?.id, filter)?)
         }
 
         Ok(LeaderBoardInfo {
@@ -377,31 +1097,243 @@ impl Db {
         })
     }
 
-    pub fn get_available_activities(&self) -> Result<Vec<Vec<ActivityInfo>>> {
-        if not_yet_started() {
+    // Points of the user at the end of `day`, from the activities of that day and before
+    fn points_at(&self, user: Uuid, day: i32) -> Result<f64> {
+        let mut activities = self.counted_activities(user)?;
+        activities.retain(|a| a.event_id <= day);
+        let scores = activities.iter().map(|a| a.score).sum();
+        let entries: Vec<ActivityEntry> = activities
+            .iter()
+            .map(|a| ActivityEntry {
+                day: a.event_id,
+                activity: a.activity,
+                distance: a.distance,
+            })
+            .collect();
+        let stats = ActivityStats::new(&entries);
+        let challenge = challenge();
+        let achievement_points = config().achievement_points.total(
+            get_achievements()
+                .iter()
+                .filter(|a| progress(&a.achievement_type, &stats, &challenge).done())
+                .map(|a| a.rank),
+        );
+        Ok(config().ranking.total(scores, achievement_points))
+    }
+
+    // Snapshots the leaderboard at the end of every finished day that has none yet, so days the
+    // server was down for are filled in as well
+    pub fn snapshot_leaderboard_if_due(&self) -> Result<()> {
+        let last = (today_unsafe(challenge_timezone()?) - 1).min(CHALLENGE_DAYS - 1);
+        let mut query = self
+            .conn
+            .prepare("SELECT DISTINCT day FROM LEADERBOARDSNAPSHOT")
+            .unwrap();
+        let done = query
+            .query_map([], |row| row.get::<_, i32>(0))?
+            .collect::<Result<Vec<i32>, _>>()?;
+        let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
+        let users = from_rows::<User>(query.query([]).unwrap())
+            .map(|user| Ok(Uuid::from_str(&user?.id)?))
+            .collect::<Result<Vec<Uuid>>>()?;
+
+        for day in (0..=last).filter(|day| !done.contains(day)) {
+            let mut points = HashMap::new();
+            for user in &users {
+                points.insert(user.to_string(), self.points_at(*user, day)?);
+            }
+            let ranks = ranks(&points);
+
+            let transaction = self.conn.unchecked_transaction()?;
+            for (user, points) in &points {
+                let snapshot = SnapshotRecord {
+                    day,
+                    user: user.clone(),
+                    points: *points,
+                    rank: ranks[user] as i64,
+                };
+                self.conn.execute(
+                    "INSERT INTO LEADERBOARDSNAPSHOT (day, user, points, rank) VALUES (:day, :user, :points, :rank)",
+                    to_params_named(&snapshot).unwrap().to_slice().as_slice(),
+                )?;
+            }
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn export(&self) -> Result<Export> {
+        let mut users = Vec::new();
+        let mut achievements = Vec::new();
+        let mut leaderboard = Vec::new();
+        let mut points = HashMap::new();
+        let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
+        for user in from_rows::<User>(query.query([]).unwrap()) {
+            let user = user?;
+            for achievement in self
+                .get_acheivements(Uuid::from_str(&user.id)?)?
+                .achievements
+            {
+                if achievement.unlocked {
+                    achievements.push(ExportAchievement {
+                        user_id: user.id.clone(),
+                        username: user.username.clone(),
+                        title: achievement.title,
+                        rank: achievement.rank,
+                    });
+                }
+            }
+            let entry =
+                self.get_user_leaderboard_entry(user.id.clone(), &LeaderboardFilter::default())?;
+            points.insert(user.username.clone(), entry.points);
+            leaderboard.push(ExportStanding {
+                rank: 0,
+                username: entry.username,
+                points: entry.points,
+                activity_points: entry.breakdown.activities,
+                achievement_points: entry.breakdown.achievements,
+                bike_dst: entry.bike_dst,
+                run_dst: entry.run_dst,
+                walk_dst: entry.walk_dst,
+                ski_dst: entry.ski_dst,
+                climb_time: entry.climb_time,
+                achievements: entry.bronze_achievements
+                    + entry.silver_achievements
+                    + entry.gold_achievements
+                    + entry.diamond_achievements,
+            });
+            users.push(ExportUser {
+                id: user.id,
+                username: user.username,
+                timezone: user.timezone,
+            });
+        }
+        let ranks = ranks(&points);
+        for standing in &mut leaderboard {
+            standing.rank = ranks[&standing.username];
+        }
+        leaderboard.sort_by(|a, b| {
+            a.rank
+                .cmp(&b.rank)
+                .then_with(|| a.username.cmp(&b.username))
+        });
+
+        let mut activities = Vec::new();
+        for activity in self.all_activities()? {
+            if !activity.status.counts() {
+                continue;
+            }
+            activities.push(ExportActivity {
+                username: self.get_user_name(&activity.user)?,
+                user_id: activity.user,
+                day: activity.event_id,
+                activity: activity.activity,
+                distance: activity.distance,
+                score: activity.score,
+                days_late: activity.days_late,
+            });
+        }
+        activities.sort_by(|a, b| a.username.cmp(&b.username).then_with(|| a.day.cmp(&b.day)));
+
+        Ok(Export {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            exported_at: Utc::now().to_rfc3339(),
+            users,
+            activities,
+            achievements,
+            leaderboard,
+        })
+    }
+
+    pub fn get_leaderboard_history(&self) -> Result<LeaderboardHistory> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM LEADERBOARDSNAPSHOT ORDER BY day")
+            .unwrap();
+        let mut snapshots = Vec::new();
+        for snapshot in from_rows::<SnapshotRecord>(query.query([]).unwrap()) {
+            let snapshot = snapshot?;
+            snapshots.push(Snapshot {
+                day: snapshot.day,
+                username: self.get_user_name(&snapshot.user)?,
+                points: snapshot.points,
+                rank: snapshot.rank as usize,
+            });
+        }
+        Ok(leaderboard_history(&snapshots))
+    }
+
+    pub fn get_available_activities(&self, user: Uuid) -> Result<Vec<Vec<ActivityInfo>>> {
+        let tz = self.get_user_timezone(user)?;
+        if not_yet_started(tz) {
             return Ok(vec![vec![]]);
         }
         let mut query = self
             .conn
             .prepare("SELECT * FROM EVENT WHERE id <= (?)")
             .unwrap();
-        let res = from_rows::<Event>(query.query([today()]).unwrap());
+        let res = from_rows::<Event>(query.query([today(tz)]).unwrap());
 
         res.into_iter()
             .map(|e| Ok(get_daily_available(e?.distance)))
             .collect()
     }
 
-    pub fn get_daily_event(&self, day: i32) -> Result<Event> {
+    // Events and doors must never be disclosed before their day, so all lookups go through here
+    fn get_daily_event(&self, day: i32, tz: Tz) -> Result<Event> {
+        if not_yet_started(tz) || day > today(tz) {
+            return Err(ClientError::forbidden("Door is not opened yet"));
+        }
         let mut query = self
             .conn
             .prepare("SELECT * FROM EVENT WHERE id = (?)")
             .unwrap();
-        let res = from_rows::<Event>(query.query([day]).unwrap());
-        for e in res {
+        let mut res = from_rows::<Event>(query.query([day]).unwrap());
+        if let Some(e) = res.next() {
             return Ok(e?);
         }
-        Err(anyhow::anyhow!("Day out of range"))
+        Err(ClientError::not_found("Day out of range"))
+    }
+
+    fn get_door_content(&self, day: i32, tz: Tz) -> Result<DoorContent> {
+        // Make sure the door is opened before reading its content
+        self.get_daily_event(day, tz)?;
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM DOOR WHERE id = (?)")
+            .unwrap();
+        let mut res = from_rows::<DoorRecord>(query.query([day]).unwrap());
+        if let Some(door) = res.next() {
+            let door = door?;
+            let bonus: Option<BonusRule> = match door.bonus {
+                Some(bonus) => Some(serde_json::from_str(&bonus)?),
+                None => None,
+            };
+            return Ok(DoorContent {
+                target: door.target,
+                message: door.message,
+                image_url: door.image_url,
+                bonus,
+            });
+        }
+        Err(ClientError::not_found("Day out of range"))
+    }
+
+    pub fn get_opened_doors(&self, user: Uuid) -> Result<Vec<Door>> {
+        let tz = self.get_user_timezone(user)?;
+        if not_yet_started(tz) {
+            return Ok(Vec::new());
+        }
+        let mut doors = Vec::new();
+        for day in 0..=today(tz) {
+            let event = self.get_daily_event(day, tz)?;
+            doors.push(Door {
+                day,
+                content: self.get_door_content(day, tz)?,
+                activities: get_daily_available(event.distance),
+            });
+        }
+        Ok(doors)
     }
 
     pub fn get_logged_activities(&self, user: Uuid) -> Result<Vec<LoggedActivityInfo>> {
@@ -415,45 +1347,73 @@ impl Db {
                     activity: a.activity,
                     value: a.distance,
                 },
+                status: a.status,
             });
         }
         Ok(activities)
     }
 
     pub fn add_activity(&self, user: Uuid, day: i32, info: ActivityInfo) -> Result<Achievements> {
-        if day < 0 || day > 23 {
-            return Err(anyhow::anyhow!(format!("Bad input day: {}", day)));
-        }
+        Validator::new()
+            .check("day", validation::day(day))
+            .check(
+                "info.value",
+                validation::activity_value(info.activity, info.value, &config().plausibility.max),
+            )
+            .finish()?;
 
-        if not_yet_started() || day > today() {
-            return Err(anyhow::anyhow!("Can not set activities in the future"));
+        let tz = self.get_user_timezone(user)?;
+        if not_yet_started(tz) || day > today(tz) {
+            return Err(ClientError::forbidden(
+                "Can not set activities in the future",
+            ));
         }
 
         let mut query = self
             .conn
             .prepare("SELECT * FROM ACTIVITYRECORD WHERE event_id = (?) AND user = (?)")
             .unwrap();
-        let res = from_rows::<Event>(
+        let res = from_rows::<ActivityRecord>(
             query
                 .query(serde_rusqlite::to_params(&(day, user.to_string())).unwrap())
                 .unwrap(),
         );
 
-        if res.count() > 0 {
-            return Err(anyhow::anyhow!("Already registered"));
+        for existing in res {
+            // A rejected activity can be logged again
+            if existing?.status != ModerationStatus::Rejected {
+                return Err(ClientError::conflict("Already registered"));
+            }
         }
 
-        let event_of_the_day = self.get_daily_event(day)?;
+        let event_of_the_day = self.get_daily_event(day, tz)?;
+        let door = self.get_door_content(day, tz)?;
 
-        let covered_dist = info.value.max(0.0);
+        let covered_dist = info.value;
 
-        let mut score = 10.0 * (covered_dist / multiplier(info.activity) as f64)
-            / event_of_the_day.distance as f64;
-        score = score.min(10.0);
+        let days_late = days_late(tz, day);
+        let late_policy = &config().late_policy;
+        let score = activity_score(
+            info.activity,
+            covered_dist,
+            event_of_the_day.distance,
+            door.bonus.as_ref(),
+            days_late,
+            late_policy,
+        )?;
 
-        if day != today() {
-            score /= 2.0;
-        }
+        let history: Vec<f64> = self
+            .counted_activities(user)?
+            .iter()
+            .filter(|a| a.activity == info.activity)
+            .map(|a| a.distance)
+            .collect();
+        let flag_reason = flag_reason(
+            info.activity,
+            covered_dist,
+            &history,
+            &config().plausibility,
+        );
 
         let record = ActivityRecord {
             user: user.to_string(),
@@ -461,16 +1421,23 @@ impl Db {
             activity: info.activity,
             score,
             distance: covered_dist,
+            days_late,
+            late_policy: serde_json::to_string(late_policy)?,
+            status: if flag_reason.is_some() {
+                ModerationStatus::Flagged
+            } else {
+                ModerationStatus::Ok
+            },
+            flag_reason: flag_reason.unwrap_or_default(),
         };
 
         let achievements_before = self.get_acheivements(user)?;
 
-        self.conn
-            .execute(
-                "INSERT INTO ACTIVITYRECORD (user, event_id, activity, score, distance) VALUES (:user, :event_id, :activity, :score, :distance)",
-                to_params_named(&record).unwrap().to_slice().as_slice(),
-            )
-            .unwrap();
+        self.conn.execute(
+            "DELETE FROM ACTIVITYRECORD WHERE event_id = (?) AND user = (?)",
+            rusqlite::params![day, user.to_string()],
+        )?;
+        self.insert_activity(&record)?;
 
         let achievements_after = self.get_acheivements(user)?;
 
@@ -508,6 +1475,174 @@ impl Db {
         }
     }
 
+    fn all_activities(&self) -> Result<Vec<ActivityRecord>> {
+        let mut query = self.conn.prepare("SELECT * FROM ACTIVITYRECORD").unwrap();
+        let res = from_rows::<ActivityRecord>(query.query([]).unwrap());
+        let mut activities: Vec<ActivityRecord> = Vec::new();
+        for activity in res {
+            activities.push(activity?);
+        }
+        Ok(activities)
+    }
+
+    fn points_per_user(&self, activities: &[ActivityRecord]) -> Result<HashMap<String, f64>> {
+        let mut points = HashMap::new();
+        let mut query = self.conn.prepare("SELECT * FROM USERS").unwrap();
+        for user in from_rows::<User>(query.query([]).unwrap()) {
+            let user = user?;
+            // Achievements do not depend on scores, so they add the same before and after
+            let achievement_points = self.get_achievement_points(Uuid::from_str(&user.id)?)?;
+            points.insert(
+                user.username,
+                config().ranking.total(0.0, achievement_points),
+            );
+        }
+        for activity in activities.iter().filter(|a| a.status.counts()) {
+            *points
+                .entry(self.get_user_name(&activity.user)?)
+                .or_insert(0.0) += activity.score;
+        }
+        Ok(points)
+    }
+
+    fn store_score_version(&self, reason: &str, activities: &[ActivityRecord]) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO SCOREVERSION (created, reason) VALUES (?, ?)",
+            [Utc::now().to_rfc3339(), reason.to_string()],
+        )?;
+        let version = self.conn.last_insert_rowid();
+        for activity in activities {
+            self.conn.execute(
+                "INSERT INTO SCOREHISTORY (version, user, event_id, score, late_policy) VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    version,
+                    activity.user,
+                    activity.event_id,
+                    activity.score,
+                    activity.late_policy
+                ],
+            )?;
+        }
+        Ok(version)
+    }
+
+    // Derives every score again from the logged distances under the current rules, but with the
+    // late policy each activity was logged with. Activities logged before the policy was stored
+    // keep their score, as their penalty is unknown, and so do flagged and rejected ones.
+    // Nothing is changed unless `commit` is set, in which case the new scores are stored as a new version.
+    pub fn recompute_scores(&self, commit: bool) -> Result<RecomputeReport> {
+        let mut distances = HashMap::new();
+        let mut query = self.conn.prepare("SELECT * FROM EVENT").unwrap();
+        for event in from_rows::<Event>(query.query([]).unwrap()) {
+            let event = event?;
+            distances.insert(event.id, event.distance);
+        }
+        let mut bonuses = HashMap::new();
+        let mut query = self.conn.prepare("SELECT * FROM DOOR").unwrap();
+        for door in from_rows::<DoorRecord>(query.query([]).unwrap()) {
+            let door = door?;
+            if let Some(bonus) = door.bonus {
+                bonuses.insert(door.id, serde_json::from_str::<BonusRule>(&bonus)?);
+            }
+        }
+
+        let old_activities = self.all_activities()?;
+        let mut new_activities = Vec::new();
+        let mut changed_scores = Vec::new();
+        for old in &old_activities {
+            if old.late_policy.is_empty() || !old.status.counts() {
+                new_activities.push(old.clone());
+                continue;
+            }
+            let late_policy = serde_json::from_str::<LatePolicy>(&old.late_policy)?;
+            let event_distance = *distances
+                .get(&old.event_id)
+                .ok_or_else(|| anyhow::anyhow!("No event for day {}", old.event_id))?;
+            // Activities the policy would have rejected no longer score
+            let score = activity_score(
+                old.activity,
+                old.distance,
+                event_distance,
+                bonuses.get(&old.event_id),
+                old.days_late,
+                &late_policy,
+            )
+            .unwrap_or(0.0);
+            if (score - old.score).abs() > f64::EPSILON {
+                changed_scores.push(ScoreChange {
+                    username: self.get_user_name(&old.user)?,
+                    day: old.event_id,
+                    old_score: old.score,
+                    new_score: score,
+                });
+            }
+            new_activities.push(ActivityRecord {
+                score,
+                ..old.clone()
+            });
+        }
+
+        let standings = standings(
+            &self.points_per_user(&old_activities)?,
+            &self.points_per_user(&new_activities)?,
+        );
+
+        let mut version = None;
+        if commit {
+            let transaction = self.conn.unchecked_transaction()?;
+            let mut query = self.conn.prepare("SELECT * FROM SCOREVERSION").unwrap();
+            if from_rows::<ScoreVersion>(query.query([]).unwrap()).count() == 0 {
+                self.store_score_version("Scores as logged", &old_activities)?;
+            }
+            version = Some(self.store_score_version("Recomputed", &new_activities)?);
+            for activity in &new_activities {
+                self.conn.execute(
+                    "UPDATE ACTIVITYRECORD SET score = (?) WHERE user = (?) AND event_id = (?)",
+                    rusqlite::params![activity.score, activity.user, activity.event_id],
+                )?;
+            }
+            transaction.commit()?;
+        }
+
+        Ok(RecomputeReport {
+            version,
+            changed_scores,
+            standings,
+        })
+    }
+
+    pub fn get_score_history(&self, user: Uuid) -> Result<Vec<ScoreHistoryInfo>> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM SCOREVERSION ORDER BY id")
+            .unwrap();
+        let versions = from_rows::<ScoreVersion>(query.query([]).unwrap());
+        let mut history = Vec::new();
+        for version in versions {
+            let version = version?;
+            let points: f64 = self.conn.query_row(
+                "SELECT COALESCE(SUM(score), 0.0) FROM SCOREHISTORY WHERE version = (?) AND user = (?)",
+                rusqlite::params![version.id, user.to_string()],
+                |row| row.get(0),
+            )?;
+            history.push(ScoreHistoryInfo {
+                version: version.id,
+                created: version.created,
+                reason: version.reason,
+                points,
+            });
+        }
+        Ok(history)
+    }
+
+    fn insert_activity(&self, record: &ActivityRecord) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO ACTIVITYRECORD (user, event_id, activity, score, distance, days_late, late_policy, status, flag_reason) VALUES (:user, :event_id, :activity, :score, :distance, :days_late, :late_policy, :status, :flag_reason)",
+            to_params_named(record).unwrap().to_slice().as_slice(),
+        )?;
+        Ok(())
+    }
+
     fn user_activities(&self, user: Uuid) -> Result<Vec<ActivityRecord>> {
         let mut query = self
             .conn
@@ -521,33 +1656,180 @@ impl Db {
         Ok(activities)
     }
 
-    pub fn get_acheivements(&self, user: Uuid) -> Result<Achievements> {
+    // Activities that are not waiting for review or rejected
+    fn counted_activities(&self, user: Uuid) -> Result<Vec<ActivityRecord>> {
         let mut activities = self.user_activities(user)?;
-        activities.sort_by_key(|activity| activity.event_id);
-        let lb = self.get_user_leaderboard_distances_and_score(user.to_string())?;
-        let mut activity_counts = HashMap::new();
-        let mut streaks = HashMap::new();
-        let mut streak_count = 1;
-        let mut last_day: Option<ActivityRecord> = None;
-        for activity in &activities {
-            let count = activity_counts.entry(activity.activity).or_insert(0);
-            *count += 1;
-
-            if let Some(yesterday) = last_day {
-                streak_count = if yesterday.activity == activity.activity
-                    && yesterday.event_id == activity.event_id - 1
-                {
-                    streak_count + 1
-                } else {
-                    1
-                }
+        activities.retain(|a| a.status.counts());
+        Ok(activities)
+    }
+
+    pub fn is_admin(&self, user: Uuid) -> Result<bool> {
+        // Users that have been deleted are no admins anymore
+        self.get_user(user)?;
+        Ok(moderation::is_admin(&config().admins, user))
+    }
+
+    pub fn get_flagged_activities(&self) -> Result<Vec<FlaggedActivity>> {
+        let mut flagged = Vec::new();
+        for activity in self.all_activities()? {
+            if activity.status != ModerationStatus::Flagged {
+                continue;
+            }
+            flagged.push(FlaggedActivity {
+                username: self.get_user_name(&activity.user)?,
+                user_id: activity.user,
+                day: activity.event_id,
+                activity: activity.activity,
+                distance: activity.distance,
+                score: activity.score,
+                reason: activity.flag_reason,
+            });
+        }
+        flagged.sort_by_key(|f| f.day);
+        Ok(flagged)
+    }
+
+    pub fn moderate_activity(&self, user: Uuid, day: i32, decision: &Decision) -> Result<()> {
+        let mut query = self
+            .conn
+            .prepare("SELECT * FROM ACTIVITYRECORD WHERE event_id = (?) AND user = (?)")
+            .unwrap();
+        let mut res = from_rows::<ActivityRecord>(
+            query
+                .query(rusqlite::params![day, user.to_string()])
+                .unwrap(),
+        );
+        let record = match res.next() {
+            Some(record) => record?,
+            None => return Err(ValidationError::field("day", "Has no logged activity")),
+        };
+        if record.status != ModerationStatus::Flagged {
+            return Err(ValidationError::field(
+                "day",
+                "Has no activity waiting for review",
+            ));
+        }
+
+        let (status, distance, score) = match decision {
+            Decision::Approve => (ModerationStatus::Approved, record.distance, record.score),
+            // Kept at zero, also if the scores are recomputed
+            Decision::Reject => (ModerationStatus::Rejected, record.distance, 0.0),
+            Decision::Adjust { value } => {
+                Validator::new()
+                    .check(
+                        "value",
+                        validation::activity_value(
+                            record.activity,
+                            *value,
+                            &config().plausibility.max,
+                        ),
+                    )
+                    .finish()?;
+                let event_distance: i32 = self.conn.query_row(
+                    "SELECT distance FROM EVENT WHERE id = (?)",
+                    [day],
+                    |row| row.get(0),
+                )?;
+                let bonus: Option<String> = self
+                    .conn
+                    .query_row("SELECT bonus FROM DOOR WHERE id = (?)", [day], |row| {
+                        row.get(0)
+                    })
+                    .unwrap_or(None);
+                let bonus = bonus
+                    .map(|b| serde_json::from_str::<BonusRule>(&b))
+                    .transpose()?;
+                // Scored under the policy it was logged with, which accepted it then
+                let late_policy = serde_json::from_str::<LatePolicy>(&record.late_policy)?;
+                let score = activity_score(
+                    record.activity,
+                    *value,
+                    event_distance,
+                    bonus.as_ref(),
+                    record.days_late,
+                    &late_policy,
+                )?;
+                (ModerationStatus::Adjusted, *value, score)
             }
+        };
+        self.conn.execute(
+            "UPDATE ACTIVITYRECORD SET status = (?), distance = (?), score = (?) WHERE event_id = (?) AND user = (?)",
+            rusqlite::params![
+                serde_json::to_value(status)?.as_str(),
+                distance,
+                score,
+                day,
+                user.to_string()
+            ],
+        )?;
+        Ok(())
+    }
 
-            last_day = Some(activity.clone());
+    fn get_activity_stats(&self, user: Uuid) -> Result<ActivityStats> {
+        let entries: Vec<ActivityEntry> = self
+            .counted_activities(user)?
+            .into_iter()
+            .map(|a| ActivityEntry {
+                day: a.event_id,
+                activity: a.activity,
+                distance: a.distance,
+            })
+            .collect();
+        Ok(ActivityStats::new(&entries))
+    }
 
-            let streak = streaks.entry(activity.activity).or_insert(0);
-            *streak = *streak.max(&mut streak_count);
+    fn get_streaks(&self, user: Uuid) -> Result<Streaks> {
+        let tz = self.get_user_timezone(user)?;
+        if not_yet_started(tz) {
+            return Ok(Streaks::default());
         }
+        Ok(self
+            .get_activity_stats(user)?
+            .streaks(&challenge(), today(tz)))
+    }
+
+    pub fn get_user_stats(&self, user: Uuid) -> Result<UserStats> {
+        let tz = self.get_user_timezone(user)?;
+        let elapsed_days = if not_yet_started(tz) {
+            0
+        } else {
+            today(tz) + 1
+        };
+        let activities: Vec<ScoredEntry> = self
+            .counted_activities(user)?
+            .into_iter()
+            .map(|a| ScoredEntry {
+                entry: ActivityEntry {
+                    day: a.event_id,
+                    activity: a.activity,
+                    distance: a.distance,
+                },
+                score: a.score,
+            })
+            .collect();
+
+        let username = self.get_user_name(&user.to_string())?;
+        let leaderboard = self.get_leaderboard(&LeaderboardFilter::default())?;
+        let all_points: Vec<f64> = leaderboard.details.iter().map(|d| d.points).collect();
+        let points = leaderboard
+            .details
+            .iter()
+            .find(|d| d.username == username)
+            .map(|d| d.points)
+            .unwrap_or(0.0);
+
+        Ok(user_stats(
+            &activities,
+            elapsed_days,
+            self.get_streaks(user)?,
+            points,
+            &all_points,
+        ))
+    }
+
+    pub fn get_acheivements(&self, user: Uuid) -> Result<Achievements> {
+        let stats = self.get_activity_stats(user)?;
+        let challenge = challenge();
 
         let all_achievemnts = get_achievements();
 
@@ -558,40 +1840,529 @@ impl Db {
         };
 
         for achievement in all_achievemnts {
-            let unlocked = match achievement.achievement_type {
-                AchievementType::Streak(times, activity) => {
-                    *streaks.entry(activity).or_default() >= times
-                }
-                AchievementType::UnlockType(types) => activity_counts.len() >= types,
-                AchievementType::ActivityCount(times, activity) => {
-                    *activity_counts.entry(activity).or_default() >= times
-                }
-                AchievementType::Distance(distance, activity) => match activity {
-                    Activity::BIKE => lb.bike_dst >= distance,
-                    Activity::SKI => lb.ski_dst >= distance,
-                    Activity::RUN => lb.run_dst >= distance,
-                    Activity::WALK => lb.run_dst >= distance,
-                    Activity::CLIMB => lb.climb_time >= distance,
-                },
-                AchievementType::FullCalender() => activities.len() == 24,
-                AchievementType::AtDate(event_id) => activities
-                    .iter()
-                    .find(|&activety| activety.event_id == event_id)
-                    .is_some(),
-            };
+            let progress = progress(&achievement.achievement_type, &stats, &challenge);
+            let unlocked = progress.done();
 
             if unlocked {
                 achievements.unlocked += 1;
             }
 
+            if achievement.secret && !unlocked {
+                achievements.achievements.push(Achievement {
+                    title: "???".to_string(),
+                    description: "Secret achievement".to_string(),
+                    unlocked,
+                    rank: achievement.rank,
+                    progress: 0.0,
+                    target: 1.0,
+                    secret: true,
+                });
+                continue;
+            }
+
             achievements.achievements.push(Achievement {
                 title: achievement.title,
                 description: achievement.description,
                 unlocked,
                 rank: achievement.rank,
+                progress: progress.current,
+                target: progress.target,
+                secret: achievement.secret,
             })
         }
 
         Ok(achievements)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> Db {
+        Db::open_in_memory().unwrap()
+    }
+
+    fn claims(sub: &str, email: &str, verified: bool) -> IdClaims {
+        serde_json::from_value(serde_json::json!({
+            "sub": sub,
+            "email": email,
+            "email_verified": verified,
+            "preferred_username": "anna",
+        }))
+        .unwrap()
+    }
+
+    // Logs like a user would, the default challenge is over so every day is open
+    fn log(db: &Db, user: Uuid, day: i32, activity: Activity, value: f64) {
+        db.add_activity(user, day, ActivityInfo { activity, value })
+            .unwrap();
+    }
+
+    fn at(tz: &str, utc: &str) -> NaiveDateTime {
+        local_time(
+            parse_timezone(tz).unwrap(),
+            DateTime::parse_from_rfc3339(utc)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    #[test]
+    fn days_in_time_zones() {
+        // The default challenge starts on 2022-12-01
+        let cases = vec![
+            ("Europe/Stockholm", "2022-12-03T22:59:59Z", 2, 2),
+            ("Europe/Stockholm", "2022-12-03T23:00:00Z", 3, 3),
+            ("America/New_York", "2022-12-04T04:59:59Z", 2, 2),
+            ("Pacific/Kiritimati", "2022-12-03T10:00:00Z", 3, 3),
+            ("UTC", "2022-11-30T12:00:00Z", -1, 0),
+            ("UTC", "2022-12-24T00:00:00Z", 23, 23),
+            ("UTC", "2023-01-10T00:00:00Z", 40, 23),
+        ];
+        for (tz, utc, day, today) in cases {
+            assert_eq!(day_at(at(tz, utc)), day, "{} {}", tz, utc);
+            assert_eq!(today_at(at(tz, utc)), today, "{} {}", tz, utc);
+        }
+    }
+
+    #[test]
+    fn days_late_with_grace() {
+        // Now, day logged, grace minutes, days late
+        let cases = vec![
+            ("2022-12-04T12:00:00Z", 3, 0, 0),
+            ("2022-12-04T12:00:00Z", 5, 0, 0),
+            ("2022-12-05T00:30:00Z", 3, 0, 1),
+            ("2022-12-05T00:30:00Z", 3, 60, 0),
+            ("2022-12-05T01:00:00Z", 3, 60, 1),
+            ("2022-12-05T00:30:00Z", 3, 1439, 0),
+            // Grace is only for yesterday
+            ("2022-12-06T00:30:00Z", 3, 60, 2),
+            ("2022-12-10T12:00:00Z", 3, 60, 6),
+        ];
+        for (utc, day, grace, late) in cases {
+            assert_eq!(
+                days_late_at(at("UTC", utc), day, grace),
+                late,
+                "{} {} {}",
+                utc,
+                day,
+                grace
+            );
+        }
+        // Late in the user's own time zone, 00:30 in Stockholm is still the 4th in UTC
+        assert_eq!(
+            days_late_at(at("Europe/Stockholm", "2022-12-04T23:30:00Z"), 3, 0),
+            1
+        );
+    }
+
+    #[test]
+    fn timezone_locked() {
+        // The default challenge has started long ago, so new users get the default time zone
+        let db = db();
+        db.add_user("anna", "password1", Some("Pacific/Kiritimati"), None)
+            .unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        assert_eq!(
+            db.get_user_timezone(anna).unwrap().name(),
+            config().default_timezone
+        );
+        assert!(db.set_user_timezone(anna, "Pacific/Kiritimati").is_err());
+        assert_eq!(
+            db.get_user_timezone(anna).unwrap().name(),
+            config().default_timezone
+        );
+        // Still checked
+        assert!(db
+            .add_user("bert", "password1", Some("Mars/Olympus"), None)
+            .is_err());
+    }
+
+    fn reset_token(db: &Db, username_or_email: &str) -> String {
+        db.create_reset_token(username_or_email).unwrap().unwrap().1
+    }
+
+    #[test]
+    fn reset_tokens() {
+        let db = db();
+        db.add_user("anna", "password1", None, Some("anna@example.com"))
+            .unwrap();
+        db.add_user("bert", "password1", None, None).unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        let old_key = db.get_session_key(anna, true).unwrap();
+
+        assert_eq!(
+            db.create_reset_token("Anna@Example.com")
+                .unwrap()
+                .unwrap()
+                .0,
+            "anna@example.com"
+        );
+        // Users without email can not reset, unknown users look the same
+        assert_eq!(db.create_reset_token("bert").unwrap(), None);
+        assert_eq!(db.create_reset_token("carl").unwrap(), None);
+
+        // Only the latest token is valid
+        let first = reset_token(&db, "anna");
+        let latest = reset_token(&db, "anna");
+        assert!(db.reset_password(&first, "password2").is_err());
+        let new_key = db.reset_password(&latest, "password2").unwrap();
+        assert!(db.get_user_id("anna", "password2").is_ok());
+
+        // Other sessions are logged out
+        assert_ne!(new_key, old_key);
+        assert!(db.get_user_from_session(old_key).is_err());
+        assert_eq!(db.get_user_from_session(new_key).unwrap(), anna);
+
+        // Single use
+        assert!(db.reset_password(&latest, "password3").is_err());
+
+        let expired = reset_token(&db, "anna");
+        db.conn
+            .execute(
+                "UPDATE RESETTOKEN SET expires = (?)",
+                [(Utc::now() - Duration::minutes(1)).to_rfc3339()],
+            )
+            .unwrap();
+        assert!(db.reset_password(&expired, "password3").is_err());
+        assert!(db.get_user_id("anna", "password2").is_ok());
+    }
+
+    #[test]
+    fn unique_emails() {
+        let db = db();
+        db.add_user("anna", "password1", None, Some("anna@example.com"))
+            .unwrap();
+        db.add_user("bert", "password1", None, None).unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        let bert = db.get_user_id("bert", "password1").unwrap();
+        assert!(db
+            .add_user("carl", "password1", None, Some("ANNA@example.com"))
+            .is_err());
+        assert!(db.set_user_email(bert, "anna@example.com").is_err());
+        assert!(db.set_user_email(anna, "Anna@example.com").is_ok());
+
+        // Shared by users of older versions, a reset would be for either of them
+        db.conn
+            .execute(
+                "UPDATE USERS SET email = 'anna@example.com' WHERE id = (?)",
+                [bert.to_string()],
+            )
+            .unwrap();
+        assert_eq!(db.create_reset_token("anna@example.com").unwrap(), None);
+        assert!(db.create_reset_token("bert").unwrap().is_some());
+    }
+
+    #[test]
+    fn recomputes_scores() {
+        let db = db();
+        db.add_user("anna", "password1", None, None).unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        let policy = serde_json::to_string(&LatePolicy::NoPenalty).unwrap();
+        // Day, score, late policy, status. Stored as is, as activities logged before late
+        // policies were stored can not be logged anymore
+        let rows = vec![
+            (0, 1.0, policy.as_str(), ModerationStatus::Ok),
+            (1, 1.0, "", ModerationStatus::Ok),
+            (2, 1.0, policy.as_str(), ModerationStatus::Flagged),
+            (3, 0.0, policy.as_str(), ModerationStatus::Rejected),
+        ];
+        for (day, score, late_policy, status) in rows {
+            db.insert_activity(&ActivityRecord {
+                user: anna.to_string(),
+                score,
+                distance: 5.0,
+                late_policy: late_policy.to_string(),
+                status,
+                ..record(Activity::RUN, day)
+            })
+            .unwrap();
+        }
+
+        let report = db.recompute_scores(true).unwrap();
+        let changed: Vec<i32> = report.changed_scores.iter().map(|c| c.day).collect();
+        assert_eq!(changed, vec![0]);
+        assert_eq!(report.version, Some(2));
+        let stored: Vec<(i32, f64, String)> = db
+            .user_activities(anna)
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.event_id, a.score, a.late_policy))
+            .collect();
+        assert_eq!(
+            stored[1..],
+            [
+                (1, 1.0, String::new()),
+                (2, 1.0, policy.clone()),
+                (3, 0.0, policy.clone())
+            ]
+        );
+        assert_eq!(stored[0].1, report.changed_scores[0].new_score);
+        assert_eq!(stored[0].2, policy);
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let dir = std::env::temp_dir().join(format!("restore-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backup = dir.join("backup.sqlite");
+        let previous = dir.join("previous.sqlite");
+
+        let mut db = db();
+        db.add_user("anna", "password1", None, None).unwrap();
+        db.backup_to(&backup).unwrap();
+        db.add_user("bert", "password1", None, None).unwrap();
+        db.restore_from(&backup, &previous).unwrap();
+        assert!(db.get_user_id("anna", "password1").is_ok());
+        assert!(db.get_user_id("bert", "password1").is_err());
+
+        // The replaced database can be restored in turn
+        db.restore_from(&previous, &dir.join("again.sqlite"))
+            .unwrap();
+        assert!(db.get_user_id("bert", "password1").is_ok());
+
+        std::fs::write(dir.join("other.sqlite"), "not a database").unwrap();
+        assert!(db
+            .restore_from(&dir.join("other.sqlite"), &previous)
+            .is_err());
+        assert!(db
+            .restore_from(&dir.join("missing.sqlite"), &previous)
+            .is_err());
+        assert!(db.get_user_id("bert", "password1").is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moderates_flagged_only() {
+        let db = db();
+        db.add_user("anna", "password1", None, None).unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        // Above the default flag limit of 45 km for the first two
+        for (day, value) in [(0, 50.0), (1, 50.0), (2, 5.0)] {
+            log(&db, anna, day, Activity::RUN, value);
+        }
+        let flagged = db.user_activities(anna).unwrap();
+        let statuses: Vec<ModerationStatus> = flagged.iter().map(|a| a.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                ModerationStatus::Flagged,
+                ModerationStatus::Flagged,
+                ModerationStatus::Ok
+            ]
+        );
+
+        db.moderate_activity(anna, 0, &Decision::Reject).unwrap();
+        db.moderate_activity(anna, 1, &Decision::Adjust { value: 1.0 })
+            .unwrap();
+        let activities = db.user_activities(anna).unwrap();
+        assert_eq!(activities[0].status, ModerationStatus::Rejected);
+        assert_eq!(activities[0].score, 0.0);
+        assert_eq!(activities[1].status, ModerationStatus::Adjusted);
+        assert_eq!(activities[1].distance, 1.0);
+        assert!(activities[1].score > 0.0);
+        assert!(activities[1].score < flagged[1].score);
+
+        // Decided once only
+        for day in [0, 1, 2, 3] {
+            assert!(db.moderate_activity(anna, day, &Decision::Approve).is_err());
+        }
+        assert!(db.get_flagged_activities().unwrap().is_empty());
+    }
+
+    fn record(activity: Activity, day: i32) -> ActivityRecord {
+        ActivityRecord {
+            user: String::new(),
+            event_id: day,
+            activity,
+            score: 1.0,
+            distance: 1.0,
+            days_late: 0,
+            late_policy: String::new(),
+            status: ModerationStatus::Ok,
+            flag_reason: String::new(),
+        }
+    }
+
+    #[test]
+    fn leaderboard_filters() {
+        let filter = |activity: Option<Activity>, from: Option<i32>, to: Option<i32>| {
+            LeaderboardFilter { activity, from, to }
+        };
+        // Filter, activity and day of the record, matches
+        let cases = vec![
+            (filter(None, None, None), Activity::RUN, 5, true),
+            (
+                filter(Some(Activity::RUN), None, None),
+                Activity::RUN,
+                5,
+                true,
+            ),
+            (
+                filter(Some(Activity::RUN), None, None),
+                Activity::SKI,
+                5,
+                false,
+            ),
+            (filter(None, Some(5), Some(5)), Activity::SKI, 5, true),
+            (filter(None, Some(6), None), Activity::SKI, 5, false),
+            (filter(None, None, Some(4)), Activity::SKI, 5, false),
+            (
+                filter(Some(Activity::CLIMB), Some(0), Some(6)),
+                Activity::CLIMB,
+                6,
+                true,
+            ),
+        ];
+        for (filter, activity, day, matches) in cases {
+            assert_eq!(
+                filter.matches(&record(activity, day)),
+                matches,
+                "{:?} {:?} {}",
+                filter,
+                activity,
+                day
+            );
+        }
+        assert!(filter(None, Some(3), Some(3)).check().is_ok());
+        assert!(filter(None, Some(4), Some(3)).check().is_err());
+        assert!(filter(None, Some(4), None).check().is_ok());
+    }
+
+    #[test]
+    fn filtered_leaderboard() {
+        let db = db();
+        db.add_user("anna", "password1", None, None).unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+        for (day, activity) in [(0, Activity::RUN), (1, Activity::SKI), (2, Activity::RUN)] {
+            log(&db, anna, day, activity, 3.0);
+        }
+        let scores: Vec<f64> = db
+            .user_activities(anna)
+            .unwrap()
+            .iter()
+            .map(|a| a.score)
+            .collect();
+        let points = |filter: LeaderboardFilter| {
+            let board = db.get_leaderboard(&filter).unwrap();
+            (board.details[0].points, board.details[0].run_dst)
+        };
+        assert_eq!(
+            points(LeaderboardFilter {
+                activity: Some(Activity::RUN),
+                ..Default::default()
+            }),
+            (scores[0] + scores[2], 6.0)
+        );
+        assert_eq!(
+            points(LeaderboardFilter {
+                from: Some(1),
+                to: Some(2),
+                ..Default::default()
+            }),
+            (scores[1] + scores[2], 3.0)
+        );
+        assert!(db
+            .get_leaderboard(&LeaderboardFilter {
+                from: Some(2),
+                to: Some(1),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn snapshots_every_day() {
+        // The default challenge is over, so every day is due
+        let db = db();
+        // Days are at least 3 km, so bert gets the full score and anna less
+        for (username, day, value) in [("anna", 0, 1.0), ("bert", 1, 40.0)] {
+            db.add_user(username, "password1", None, None).unwrap();
+            let user = db.get_user_id(username, "password1").unwrap();
+            log(&db, user, day, Activity::RUN, value);
+        }
+        let ranks = |db: &Db| {
+            let mut query = db
+                .conn
+                .prepare("SELECT * FROM LEADERBOARDSNAPSHOT ORDER BY day, rank")
+                .unwrap();
+            from_rows::<SnapshotRecord>(query.query([]).unwrap())
+                .map(|s| {
+                    let s = s.unwrap();
+                    (s.day, db.get_user_name(&s.user).unwrap(), s.rank)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        db.snapshot_leaderboard_if_due().unwrap();
+        let snapshots = ranks(&db);
+        assert_eq!(snapshots.len(), 2 * CHALLENGE_DAYS as usize);
+        // Bert's activity of day 1 is not in the snapshot of day 0
+        assert_eq!(
+            snapshots[..4],
+            [
+                (0, "anna".to_string(), 1),
+                (0, "bert".to_string(), 2),
+                (1, "bert".to_string(), 1),
+                (1, "anna".to_string(), 2),
+            ]
+        );
+
+        db.conn
+            .execute("DELETE FROM LEADERBOARDSNAPSHOT WHERE day = 5", [])
+            .unwrap();
+        db.snapshot_leaderboard_if_due().unwrap();
+        assert_eq!(ranks(&db), snapshots);
+    }
+
+    #[test]
+    fn oidc_users() {
+        let db = db();
+        db.add_user("anna", "password1", None, Some("anna@example.com"))
+            .unwrap();
+        let anna = db.get_user_id("anna", "password1").unwrap();
+
+        // Never linked by email, the new user gets neither the name nor the email
+        let sso = db
+            .oidc_user("idp", &claims("1", "anna@example.com", true), None)
+            .unwrap();
+        assert_ne!(sso, anna);
+        assert_eq!(db.get_user(sso).unwrap().username, "anna2");
+        assert_eq!(db.get_user(sso).unwrap().email, "");
+        assert_eq!(
+            db.oidc_user("idp", &claims("1", "anna@example.com", true), None)
+                .unwrap(),
+            sso
+        );
+
+        // Linked by the logged in user
+        let linked = claims("2", "other@example.com", true);
+        assert_eq!(db.oidc_user("idp", &linked, Some(anna)).unwrap(), anna);
+        assert_eq!(db.oidc_user("idp", &linked, None).unwrap(), anna);
+        assert!(db.oidc_user("idp", &linked, Some(sso)).is_err());
+
+        let unverified = db
+            .oidc_user("idp", &claims("3", "bert@example.com", false), None)
+            .unwrap();
+        assert_eq!(db.get_user(unverified).unwrap().email, "");
+        let verified = db
+            .oidc_user("idp", &claims("4", "bert@example.com", true), None)
+            .unwrap();
+        assert_eq!(db.get_user(verified).unwrap().email, "bert@example.com");
+
+        // Without a password only for users created by single sign-on that never set one
+        assert!(db.delete_user(sso, "").is_ok());
+        assert!(db.get_user(sso).is_err());
+        db.add_user("carl", "password1", None, None).unwrap();
+        let carl = db.get_user_id("carl", "password1").unwrap();
+        assert!(db.delete_user(carl, "").is_err());
+        assert!(db.delete_user(anna, "").is_err());
+        assert!(db.delete_user(anna, "password1").is_ok());
+
+        let (_, token) = db.create_reset_token("bert@example.com").unwrap().unwrap();
+        db.reset_password(&token, "password2").unwrap();
+        assert!(db.delete_user(verified, "").is_err());
+        assert!(db.delete_user(verified, "password2").is_ok());
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::{Activity, ActivityInfo};
+
+// Optional file with the content of every door, one entry per day in order
+const DOORS_FILE: &str = "doors.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BonusRule {
+    // Score of the given activity is multiplied on the day of the door
+    ActivityMultiplier(Activity, f64),
+    // Flat bonus added to any activity logged on the day of the door
+    Flat(f64),
+}
+
+impl BonusRule {
+    pub fn apply(&self, activity: Activity, score: f64) -> f64 {
+        match self {
+            BonusRule::ActivityMultiplier(bonus_activity, factor) => {
+                if *bonus_activity == activity {
+                    score * factor
+                } else {
+                    score
+                }
+            }
+            BonusRule::Flat(bonus) => score + bonus,
+        }
+    }
+
+    // Scores must never turn negative, whatever the bonus
+    fn check(&self) -> Result<()> {
+        match self {
+            BonusRule::ActivityMultiplier(_, factor) if *factor <= 0.0 => Err(anyhow::anyhow!(
+                "Bonus factor must be above 0, not {}",
+                factor
+            )),
+            BonusRule::Flat(bonus) if *bonus < 0.0 => Err(anyhow::anyhow!(
+                "Flat bonus must be at least 0, not {}",
+                bonus
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DoorContent {
+    pub target: String,
+    pub message: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub bonus: Option<BonusRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Door {
+    pub day: i32,
+    pub content: DoorContent,
+    pub activities: Vec<ActivityInfo>,
+}
+
+fn default_content(distance: i32) -> DoorContent {
+    DoorContent {
+        target: format!("Cover {} km (or the equivalent in any activity)", distance),
+        message: "Gah! Another day, another door.".to_string(),
+        image_url: None,
+        bonus: None,
+    }
+}
+
+// Content for each day, taken from DOORS_FILE if present, otherwise generated from the distances
+pub fn door_contents(distances: &[i32]) -> Result<Vec<DoorContent>> {
+    if !Path::new(DOORS_FILE).exists() {
+        return Ok(distances.iter().map(|d| default_content(*d)).collect());
+    }
+
+    parse_contents(&std::fs::read(DOORS_FILE)?, distances)
+}
+
+fn parse_contents(json: &[u8], distances: &[i32]) -> Result<Vec<DoorContent>> {
+    let contents: Vec<DoorContent> = serde_json::from_slice(json)?;
+    if contents.len() != distances.len() {
+        return Err(anyhow::anyhow!(
+            "{} has {} doors, expected {}",
+            DOORS_FILE,
+            contents.len(),
+            distances.len()
+        ));
+    }
+    for (day, content) in contents.iter().enumerate() {
+        if let Some(bonus) = &content.bonus {
+            bonus
+                .check()
+                .map_err(|e| anyhow::anyhow!("Door {} in {}: {}", day + 1, DOORS_FILE, e))?;
+        }
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Activity::*;
+
+    #[test]
+    fn bonus_rules() {
+        let cases = vec![
+            (BonusRule::ActivityMultiplier(SKI, 2.0), SKI, 3.0, 6.0),
+            (BonusRule::ActivityMultiplier(SKI, 2.0), RUN, 3.0, 3.0),
+            (BonusRule::Flat(1.5), RUN, 3.0, 4.5),
+            (BonusRule::Flat(1.5), CLIMB, 0.0, 1.5),
+        ];
+        for (rule, activity, score, expected) in cases {
+            assert_eq!(
+                rule.apply(activity, score),
+                expected,
+                "{:?} {:?}",
+                rule,
+                activity
+            );
+        }
+    }
+
+    #[test]
+    fn contents() {
+        // Without a doors file the content is generated from the distances
+        let contents = door_contents(&[5, 10]).unwrap();
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[1], default_content(10));
+        assert!(contents[0].target.contains("5 km"));
+        assert_eq!(contents[0].bonus, None);
+
+        let content: DoorContent = serde_json::from_str(
+            r#"{"target": "Ski 10 km", "message": "Snow!", "bonus": {"Flat": 2.0}}"#,
+        )
+        .unwrap();
+        assert_eq!(content.image_url, None);
+        assert_eq!(content.bonus, Some(BonusRule::Flat(2.0)));
+
+        let door = |bonus: &str| {
+            format!(
+                r#"{{"target": "Ski", "message": "Snow!", "bonus": {}}}"#,
+                bonus
+            )
+        };
+        let cases = vec![
+            (r#"{"Flat": 0.0}"#, true),
+            (r#"{"Flat": 2.0}"#, true),
+            (r#"{"Flat": -1.0}"#, false),
+            (r#"{"ActivityMultiplier": ["SKI", 2.0]}"#, true),
+            (r#"{"ActivityMultiplier": ["SKI", 0.0]}"#, false),
+            (r#"{"ActivityMultiplier": ["SKI", -2.0]}"#, false),
+            ("null", true),
+        ];
+        for (bonus, valid) in cases {
+            let json = format!("[{}, {}]", door("null"), door(bonus));
+            assert_eq!(
+                parse_contents(json.as_bytes(), &[5, 10]).is_ok(),
+                valid,
+                "{}",
+                bonus
+            );
+        }
+        // One door per day
+        let json = format!("[{}]", door("null"));
+        assert!(parse_contents(json.as_bytes(), &[5, 10]).is_err());
+    }
+}
@@ -0,0 +1,728 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde_derive::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::achievements::AchievementType;
+use crate::db::Activity;
+
+// What the evaluator needs to know about a logged activity
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityEntry {
+    pub day: i32,
+    pub activity: Activity,
+    pub distance: f64,
+}
+
+// Days without activity that do not break a streak
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct StreakRules {
+    // Weekdays that never break a streak, e.g. ["Sun"]
+    pub rest_days: Vec<Weekday>,
+    // Number of missed days forgiven within one streak
+    pub freezes: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Streaks {
+    pub current: i32,
+    pub longest: i32,
+}
+
+// The challenge the activities were logged in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub start_date: NaiveDate,
+    pub days: i32,
+    pub streak_rules: StreakRules,
+}
+
+impl Challenge {
+    fn is_rest_day(&self, day: i32) -> bool {
+        let weekday = (self.start_date + Duration::days(day as i64)).weekday();
+        self.streak_rules.rest_days.contains(&weekday)
+    }
+
+    // Streaks of active days up to and including `today`. Not having logged today yet does not
+    // break the current streak, and rest days and freezes bridge a streak without adding to it.
+    pub fn streaks(&self, days: &BTreeSet<i32>, today: i32) -> Streaks {
+        let mut streaks = Streaks::default();
+        let mut freezes_left = self.streak_rules.freezes;
+        for day in 0..=today.min(self.days - 1) {
+            if days.contains(&day) {
+                streaks.current += 1;
+                streaks.longest = streaks.longest.max(streaks.current);
+            } else if day == today || self.is_rest_day(day) {
+                continue;
+            } else if streaks.current > 0 && freezes_left > 0 {
+                freezes_left -= 1;
+            } else {
+                streaks.current = 0;
+                freezes_left = self.streak_rules.freezes;
+            }
+        }
+        streaks
+    }
+
+    // Day of the challenge falling on the given month and day, if any
+    pub fn day_of(&self, month: u32, day: u32) -> Option<i32> {
+        let mut date = NaiveDate::from_ymd_opt(self.start_date.year(), month, day)?;
+        if date < self.start_date {
+            date = NaiveDate::from_ymd_opt(self.start_date.year() + 1, month, day)?;
+        }
+        let index = (date - self.start_date).num_days() as i32;
+        if index < self.days {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+// Aggregates of a user's activities that the achievements are evaluated against
+#[derive(Debug, Default)]
+pub struct ActivityStats {
+    entries: Vec<ActivityEntry>,
+    days: BTreeSet<i32>,
+    days_per_activity: HashMap<Activity, BTreeSet<i32>>,
+    counts: HashMap<Activity, i32>,
+    distances: HashMap<Activity, f64>,
+}
+
+impl ActivityStats {
+    pub fn new(activities: &[ActivityEntry]) -> Self {
+        let mut stats = ActivityStats {
+            entries: activities.to_vec(),
+            ..Default::default()
+        };
+        for entry in activities {
+            stats.days.insert(entry.day);
+            stats
+                .days_per_activity
+                .entry(entry.activity)
+                .or_default()
+                .insert(entry.day);
+            *stats.counts.entry(entry.activity).or_default() += 1;
+            *stats.distances.entry(entry.activity).or_default() += entry.distance;
+        }
+        stats
+    }
+
+    pub fn count(&self, activity: Activity) -> i32 {
+        *self.counts.get(&activity).unwrap_or(&0)
+    }
+
+    pub fn distance(&self, activity: Activity) -> f64 {
+        *self.distances.get(&activity).unwrap_or(&0.0)
+    }
+
+    pub fn distinct_types(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn longest_streak(&self, activity: Activity, challenge: &Challenge) -> i32 {
+        self.days_per_activity
+            .get(&activity)
+            .map(|days| challenge.streaks(days, challenge.days - 1).longest)
+            .unwrap_or(0)
+    }
+
+    // Streaks over any activity
+    pub fn streaks(&self, challenge: &Challenge, today: i32) -> Streaks {
+        challenge.streaks(&self.days, today)
+    }
+
+    pub fn active_on(&self, day: i32) -> bool {
+        self.days.contains(&day)
+    }
+
+    pub fn active_days(&self) -> i32 {
+        self.days.len() as i32
+    }
+
+    // Stats of the activities from `first` up to but not including `first + days`
+    fn window(&self, first: i32, days: i32) -> ActivityStats {
+        let entries: Vec<ActivityEntry> = self
+            .entries
+            .iter()
+            .filter(|e| e.day >= first && e.day < first + days)
+            .cloned()
+            .collect();
+        ActivityStats::new(&entries)
+    }
+}
+
+pub fn all_activity_types() -> usize {
+    Activity::iter().count()
+}
+
+// How far a user has come towards an achievement, e.g. 7 of 10 runs or 63 of 100 km
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub current: f64,
+    pub target: f64,
+}
+
+impl Progress {
+    fn of(current: impl Into<f64>, target: impl Into<f64>) -> Self {
+        Progress {
+            current: current.into(),
+            target: target.into(),
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.current >= self.target
+    }
+
+    // Fraction of the target reached, at most 1
+    fn ratio(&self) -> f64 {
+        if self.target <= 0.0 {
+            return 1.0;
+        }
+        (self.current / self.target).min(1.0)
+    }
+}
+
+pub fn progress(
+    achievement_type: &AchievementType,
+    stats: &ActivityStats,
+    challenge: &Challenge,
+) -> Progress {
+    match achievement_type {
+        AchievementType::UnlockType(types) => {
+            Progress::of(stats.distinct_types() as f64, *types as f64)
+        }
+        AchievementType::Streak(days, activity) => {
+            Progress::of(stats.longest_streak(*activity, challenge), *days)
+        }
+        AchievementType::AnyStreak(days) => {
+            Progress::of(stats.streaks(challenge, challenge.days - 1).longest, *days)
+        }
+        AchievementType::Distance(distance, activity) => {
+            Progress::of(stats.distance(*activity), *distance)
+        }
+        AchievementType::ActivityCount(times, activity) => {
+            Progress::of(stats.count(*activity), *times)
+        }
+        AchievementType::AtDate(month, day) => {
+            let active = challenge
+                .day_of(*month, *day)
+                .map(|day| stats.active_on(day))
+                .unwrap_or(false);
+            Progress::of(if active { 1 } else { 0 }, 1)
+        }
+        AchievementType::FullCalender() => Progress::of(stats.active_days(), challenge.days),
+        AchievementType::All(criteria) => Progress::of(
+            criteria
+                .iter()
+                .map(|c| progress(c, stats, challenge).ratio())
+                .sum::<f64>(),
+            criteria.len() as f64,
+        ),
+        AchievementType::Any(criteria) => Progress::of(
+            criteria
+                .iter()
+                .map(|c| progress(c, stats, challenge).ratio())
+                .fold(0.0, f64::max),
+            1.0,
+        ),
+        AchievementType::Within(days, criterion) => (0..=(challenge.days - days).max(0))
+            .map(|first| progress(criterion, &stats.window(first, *days), challenge))
+            .max_by(|a, b| a.ratio().total_cmp(&b.ratio()))
+            .unwrap_or(Progress::of(0, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Activity::*;
+
+    fn challenge() -> Challenge {
+        Challenge {
+            start_date: NaiveDate::from_ymd_opt(2022, 12, 1).unwrap(),
+            days: 24,
+            streak_rules: StreakRules::default(),
+        }
+    }
+
+    fn entry(day: i32, activity: Activity, distance: f64) -> ActivityEntry {
+        ActivityEntry {
+            day,
+            activity,
+            distance,
+        }
+    }
+
+    fn every_day(activity: Activity) -> Vec<ActivityEntry> {
+        (0..24).map(|day| entry(day, activity, 1.0)).collect()
+    }
+
+    #[test]
+    fn achievement_types() {
+        let cases: Vec<(&str, AchievementType, Vec<ActivityEntry>, bool)> = vec![
+            (
+                "no activities",
+                AchievementType::UnlockType(1),
+                vec![],
+                false,
+            ),
+            (
+                "one type",
+                AchievementType::UnlockType(1),
+                vec![entry(0, RUN, 1.0)],
+                true,
+            ),
+            (
+                "same type twice is one type",
+                AchievementType::UnlockType(2),
+                vec![entry(0, RUN, 1.0), entry(1, RUN, 1.0)],
+                false,
+            ),
+            (
+                "four of five types is not all",
+                AchievementType::UnlockType(all_activity_types()),
+                vec![
+                    entry(0, RUN, 1.0),
+                    entry(1, BIKE, 1.0),
+                    entry(2, WALK, 1.0),
+                    entry(3, SKI, 1.0),
+                ],
+                false,
+            ),
+            (
+                "all five types",
+                AchievementType::UnlockType(all_activity_types()),
+                vec![
+                    entry(0, RUN, 1.0),
+                    entry(1, BIKE, 1.0),
+                    entry(2, WALK, 1.0),
+                    entry(3, SKI, 1.0),
+                    entry(4, CLIMB, 1.0),
+                ],
+                true,
+            ),
+            (
+                "count reached",
+                AchievementType::ActivityCount(3, SKI),
+                vec![entry(0, SKI, 1.0), entry(5, SKI, 1.0), entry(9, SKI, 1.0)],
+                true,
+            ),
+            (
+                "count of other activity does not help",
+                AchievementType::ActivityCount(3, SKI),
+                vec![entry(0, SKI, 1.0), entry(5, SKI, 1.0), entry(9, RUN, 1.0)],
+                false,
+            ),
+            (
+                "distance summed over days",
+                AchievementType::Distance(42.0, RUN),
+                vec![entry(0, RUN, 21.0), entry(3, RUN, 21.0)],
+                true,
+            ),
+            (
+                "distance just short",
+                AchievementType::Distance(42.0, RUN),
+                vec![entry(0, RUN, 21.0), entry(3, RUN, 20.9)],
+                false,
+            ),
+            (
+                "walk distance counts walks",
+                AchievementType::Distance(10.0, WALK),
+                vec![entry(0, WALK, 10.0)],
+                true,
+            ),
+            (
+                "walk distance ignores runs",
+                AchievementType::Distance(10.0, WALK),
+                vec![entry(0, RUN, 10.0)],
+                false,
+            ),
+            (
+                "climb time",
+                AchievementType::Distance(600.0, CLIMB),
+                vec![entry(0, CLIMB, 300.0), entry(1, CLIMB, 300.0)],
+                true,
+            ),
+            (
+                "streak of consecutive days",
+                AchievementType::Streak(3, BIKE),
+                vec![
+                    entry(4, BIKE, 1.0),
+                    entry(5, BIKE, 1.0),
+                    entry(6, BIKE, 1.0),
+                ],
+                true,
+            ),
+            (
+                "streak independent of logging order",
+                AchievementType::Streak(3, BIKE),
+                vec![
+                    entry(6, BIKE, 1.0),
+                    entry(4, BIKE, 1.0),
+                    entry(5, BIKE, 1.0),
+                ],
+                true,
+            ),
+            (
+                "streak broken by a gap",
+                AchievementType::Streak(3, BIKE),
+                vec![
+                    entry(4, BIKE, 1.0),
+                    entry(5, BIKE, 1.0),
+                    entry(7, BIKE, 1.0),
+                ],
+                false,
+            ),
+            (
+                "streak broken by other activity",
+                AchievementType::Streak(3, BIKE),
+                vec![entry(4, BIKE, 1.0), entry(5, RUN, 1.0), entry(6, BIKE, 1.0)],
+                false,
+            ),
+            (
+                "longest streak counts, not the last one",
+                AchievementType::Streak(3, BIKE),
+                vec![
+                    entry(0, BIKE, 1.0),
+                    entry(1, BIKE, 1.0),
+                    entry(2, BIKE, 1.0),
+                    entry(10, BIKE, 1.0),
+                ],
+                true,
+            ),
+            (
+                "any activity streak mixes activities",
+                AchievementType::AnyStreak(3),
+                vec![entry(4, BIKE, 1.0), entry(5, RUN, 1.0), entry(6, BIKE, 1.0)],
+                true,
+            ),
+            (
+                "any activity streak broken by a gap",
+                AchievementType::AnyStreak(3),
+                vec![entry(4, BIKE, 1.0), entry(5, RUN, 1.0), entry(7, BIKE, 1.0)],
+                false,
+            ),
+            (
+                "all criteria met",
+                AchievementType::All(vec![
+                    AchievementType::ActivityCount(1, RUN),
+                    AchievementType::ActivityCount(1, BIKE),
+                ]),
+                vec![entry(0, RUN, 1.0), entry(20, BIKE, 1.0)],
+                true,
+            ),
+            (
+                "all criteria with one missing",
+                AchievementType::All(vec![
+                    AchievementType::ActivityCount(1, RUN),
+                    AchievementType::ActivityCount(1, BIKE),
+                ]),
+                vec![entry(0, RUN, 1.0), entry(20, RUN, 1.0)],
+                false,
+            ),
+            (
+                "any criterion met",
+                AchievementType::Any(vec![
+                    AchievementType::ActivityCount(1, SKI),
+                    AchievementType::ActivityCount(1, CLIMB),
+                ]),
+                vec![entry(0, CLIMB, 1.0)],
+                true,
+            ),
+            (
+                "no criterion met",
+                AchievementType::Any(vec![
+                    AchievementType::ActivityCount(1, SKI),
+                    AchievementType::ActivityCount(1, CLIMB),
+                ]),
+                vec![entry(0, RUN, 1.0)],
+                false,
+            ),
+            (
+                "within a week",
+                AchievementType::Within(
+                    7,
+                    Box::new(AchievementType::All(vec![
+                        AchievementType::ActivityCount(1, RUN),
+                        AchievementType::ActivityCount(1, BIKE),
+                    ])),
+                ),
+                vec![entry(2, RUN, 1.0), entry(8, BIKE, 1.0)],
+                true,
+            ),
+            (
+                "not within a week",
+                AchievementType::Within(
+                    7,
+                    Box::new(AchievementType::All(vec![
+                        AchievementType::ActivityCount(1, RUN),
+                        AchievementType::ActivityCount(1, BIKE),
+                    ])),
+                ),
+                vec![entry(2, RUN, 1.0), entry(9, BIKE, 1.0)],
+                false,
+            ),
+            (
+                "all types in five consecutive days",
+                AchievementType::Within(5, Box::new(AchievementType::UnlockType(5))),
+                vec![
+                    entry(19, RUN, 1.0),
+                    entry(20, BIKE, 1.0),
+                    entry(21, WALK, 1.0),
+                    entry(22, SKI, 1.0),
+                    entry(23, CLIMB, 1.0),
+                ],
+                true,
+            ),
+            (
+                "all types spread over six days",
+                AchievementType::Within(5, Box::new(AchievementType::UnlockType(5))),
+                vec![
+                    entry(0, RUN, 1.0),
+                    entry(1, BIKE, 1.0),
+                    entry(2, WALK, 1.0),
+                    entry(3, SKI, 1.0),
+                    entry(5, CLIMB, 1.0),
+                ],
+                false,
+            ),
+            (
+                "christmas eve",
+                AchievementType::AtDate(12, 24),
+                vec![entry(23, WALK, 1.0)],
+                true,
+            ),
+            (
+                "day before christmas eve",
+                AchievementType::AtDate(12, 24),
+                vec![entry(22, WALK, 1.0)],
+                false,
+            ),
+            (
+                "full calendar",
+                AchievementType::FullCalender(),
+                every_day(RUN),
+                true,
+            ),
+            (
+                "full calendar missing a day",
+                AchievementType::FullCalender(),
+                every_day(RUN).into_iter().skip(1).collect(),
+                false,
+            ),
+        ];
+
+        for (name, achievement_type, activities, expected) in cases {
+            let stats = ActivityStats::new(&activities);
+            assert_eq!(
+                progress(&achievement_type, &stats, &challenge()).done(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn progress_towards_achievements() {
+        let activities = vec![
+            entry(0, RUN, 10.0),
+            entry(1, RUN, 11.5),
+            entry(2, BIKE, 30.0),
+            entry(4, RUN, 5.0),
+        ];
+        let cases: Vec<(AchievementType, Progress)> = vec![
+            (AchievementType::UnlockType(1), Progress::of(2, 1)),
+            (
+                AchievementType::UnlockType(all_activity_types()),
+                Progress::of(2, 5),
+            ),
+            (AchievementType::Streak(3, RUN), Progress::of(2, 3)),
+            (AchievementType::Streak(3, SKI), Progress::of(0, 3)),
+            (AchievementType::AnyStreak(5), Progress::of(3, 5)),
+            (
+                AchievementType::Distance(42.0, RUN),
+                Progress::of(26.5, 42.0),
+            ),
+            (
+                AchievementType::Distance(100.0, BIKE),
+                Progress::of(30.0, 100.0),
+            ),
+            (AchievementType::ActivityCount(10, RUN), Progress::of(3, 10)),
+            (AchievementType::ActivityCount(1, CLIMB), Progress::of(0, 1)),
+            (AchievementType::AtDate(12, 2), Progress::of(1, 1)),
+            (AchievementType::AtDate(12, 24), Progress::of(0, 1)),
+            (AchievementType::FullCalender(), Progress::of(4, 24)),
+            (
+                AchievementType::All(vec![
+                    AchievementType::ActivityCount(1, BIKE),
+                    AchievementType::ActivityCount(4, RUN),
+                ]),
+                Progress::of(1.75, 2),
+            ),
+            (
+                AchievementType::Any(vec![
+                    AchievementType::ActivityCount(4, RUN),
+                    AchievementType::ActivityCount(2, BIKE),
+                ]),
+                Progress::of(0.75, 1),
+            ),
+            (
+                AchievementType::Within(2, Box::new(AchievementType::ActivityCount(3, RUN))),
+                Progress::of(2, 3),
+            ),
+        ];
+
+        let stats = ActivityStats::new(&activities);
+        for (achievement_type, expected) in cases {
+            assert_eq!(
+                progress(&achievement_type, &stats, &challenge()),
+                expected,
+                "{:?}",
+                achievement_type
+            );
+        }
+    }
+
+    #[test]
+    fn dates_follow_the_start_of_the_challenge() {
+        let late_start = Challenge {
+            start_date: NaiveDate::from_ymd_opt(2023, 12, 10).unwrap(),
+            days: 24,
+            streak_rules: StreakRules::default(),
+        };
+        assert_eq!(challenge().day_of(12, 24), Some(23));
+        assert_eq!(late_start.day_of(12, 24), Some(14));
+        assert_eq!(late_start.day_of(1, 2), Some(23));
+        assert_eq!(late_start.day_of(1, 3), None);
+        assert_eq!(late_start.day_of(12, 1), None);
+
+        let stats = ActivityStats::new(&[entry(14, RUN, 1.0)]);
+        let christmas_eve = AchievementType::AtDate(12, 24);
+        assert!(progress(&christmas_eve, &stats, &late_start).done());
+        assert!(!progress(&christmas_eve, &stats, &challenge()).done());
+    }
+
+    #[test]
+    fn streaks() {
+        // 2022-12-01 was a Thursday, so days 3, 10 and 17 are Sundays
+        let sundays_off = StreakRules {
+            rest_days: vec![Weekday::Sun],
+            freezes: 0,
+        };
+        let one_freeze = StreakRules {
+            rest_days: vec![],
+            freezes: 1,
+        };
+        let cases: Vec<(&str, StreakRules, Vec<i32>, i32, Streaks)> = vec![
+            (
+                "nothing logged",
+                StreakRules::default(),
+                vec![],
+                23,
+                streaks_of(0, 0),
+            ),
+            (
+                "single day",
+                StreakRules::default(),
+                vec![3],
+                23,
+                streaks_of(0, 1),
+            ),
+            (
+                "ongoing",
+                StreakRules::default(),
+                vec![1, 2, 3],
+                3,
+                streaks_of(3, 3),
+            ),
+            (
+                "today not logged yet",
+                StreakRules::default(),
+                vec![1, 2, 3],
+                4,
+                streaks_of(3, 3),
+            ),
+            (
+                "yesterday missed",
+                StreakRules::default(),
+                vec![1, 2, 3],
+                5,
+                streaks_of(0, 3),
+            ),
+            (
+                "longest is not the last",
+                StreakRules::default(),
+                vec![0, 1, 5, 6, 7, 8, 20],
+                20,
+                streaks_of(1, 4),
+            ),
+            (
+                "future days are ignored",
+                StreakRules::default(),
+                vec![1, 2, 3],
+                2,
+                streaks_of(2, 2),
+            ),
+            (
+                "rest day bridges",
+                sundays_off.clone(),
+                vec![1, 2, 4, 5],
+                5,
+                streaks_of(4, 4),
+            ),
+            (
+                "rest day only bridges sundays",
+                sundays_off.clone(),
+                vec![1, 2, 3, 5, 6],
+                6,
+                streaks_of(2, 3),
+            ),
+            (
+                "activity on rest day counts",
+                sundays_off,
+                vec![2, 3, 4],
+                4,
+                streaks_of(3, 3),
+            ),
+            (
+                "freeze bridges one missed day",
+                one_freeze.clone(),
+                vec![1, 2, 4, 5],
+                5,
+                streaks_of(4, 4),
+            ),
+            (
+                "freeze does not bridge two missed days",
+                one_freeze.clone(),
+                vec![1, 2, 4, 6, 7],
+                7,
+                streaks_of(2, 3),
+            ),
+            (
+                "freezes are restored for a new streak",
+                one_freeze,
+                vec![0, 3, 5, 6],
+                6,
+                streaks_of(3, 3),
+            ),
+        ];
+
+        for (name, rules, days, today, expected) in cases {
+            let challenge = Challenge {
+                streak_rules: rules,
+                ..challenge()
+            };
+            let set: BTreeSet<i32> = days.iter().cloned().collect();
+            assert_eq!(challenge.streaks(&set, today), expected, "{}", name);
+        }
+    }
+
+    fn streaks_of(current: i32, longest: i32) -> Streaks {
+        Streaks { current, longest }
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_derive::Deserialize;
+
+use crate::achievements::AchievementRank;
+use crate::db::{Activity, ScoreHistoryInfo};
+use crate::stats::UserStats;
+
+// Bumped whenever a field is renamed or removed, see the README for the schema
+pub const EXPORT_SCHEMA_VERSION: i32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportUser {
+    pub id: String,
+    pub username: String,
+    pub timezone: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportActivity {
+    pub user_id: String,
+    pub username: String,
+    pub day: i32,
+    pub activity: Activity,
+    pub distance: f64,
+    pub score: f64,
+    pub days_late: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportAchievement {
+    pub user_id: String,
+    pub username: String,
+    pub title: String,
+    pub rank: AchievementRank,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExportStanding {
+    pub rank: usize,
+    pub username: String,
+    pub points: f64,
+    pub activity_points: f64,
+    pub achievement_points: f64,
+    pub bike_dst: f64,
+    pub run_dst: f64,
+    pub walk_dst: f64,
+    pub ski_dst: f64,
+    pub climb_time: f64,
+    pub achievements: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Export {
+    pub schema_version: i32,
+    pub exported_at: String,
+    pub users: Vec<ExportUser>,
+    pub activities: Vec<ExportActivity>,
+    pub achievements: Vec<ExportAchievement>,
+    pub leaderboard: Vec<ExportStanding>,
+}
+
+// Everything stored about a single user
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PersonalData {
+    pub schema_version: i32,
+    pub exported_at: String,
+    pub user: ExportUser,
+    pub email: String,
+    pub activities: Vec<ExportActivity>,
+    pub achievements: Vec<ExportAchievement>,
+    pub stats: UserStats,
+    pub score_history: Vec<ScoreHistoryInfo>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportTable {
+    Users,
+    Activities,
+    Achievements,
+    Leaderboard,
+}
+
+impl ExportTable {
+    pub fn all() -> [ExportTable; 4] {
+        [
+            ExportTable::Users,
+            ExportTable::Activities,
+            ExportTable::Achievements,
+            ExportTable::Leaderboard,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportTable::Users => "users",
+            ExportTable::Activities => "activities",
+            ExportTable::Achievements => "achievements",
+            ExportTable::Leaderboard => "leaderboard",
+        }
+    }
+}
+
+fn rows_to_csv<T: Serialize>(rows: &[T]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+impl Export {
+    pub fn to_csv(&self, table: ExportTable) -> Result<String> {
+        match table {
+            ExportTable::Users => rows_to_csv(&self.users),
+            ExportTable::Activities => rows_to_csv(&self.activities),
+            ExportTable::Achievements => rows_to_csv(&self.achievements),
+            ExportTable::Leaderboard => rows_to_csv(&self.leaderboard),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export() -> Export {
+        Export {
+            schema_version: EXPORT_SCHEMA_VERSION,
+            exported_at: "2022-12-24T12:00:00+00:00".to_string(),
+            users: vec![ExportUser {
+                id: "u1".to_string(),
+                username: "tomte".to_string(),
+                timezone: String::new(),
+            }],
+            activities: vec![ExportActivity {
+                user_id: "u1".to_string(),
+                username: "tomte".to_string(),
+                day: 3,
+                activity: Activity::RUN,
+                distance: 5.5,
+                score: 11.0,
+                days_late: 1,
+            }],
+            achievements: vec![ExportAchievement {
+                user_id: "u1".to_string(),
+                username: "tomte".to_string(),
+                title: "First steps, again".to_string(),
+                rank: AchievementRank::Bronze,
+            }],
+            leaderboard: vec![ExportStanding {
+                rank: 1,
+                username: "tomte".to_string(),
+                points: 13.0,
+                activity_points: 11.0,
+                achievement_points: 2.0,
+                bike_dst: 0.0,
+                run_dst: 5.5,
+                walk_dst: 0.0,
+                ski_dst: 0.0,
+                climb_time: 0.0,
+                achievements: 1,
+            }],
+        }
+    }
+
+    #[test]
+    fn csv_tables() {
+        let export = export();
+        let cases = vec![
+            (ExportTable::Users, "id,username,timezone\nu1,tomte,\n"),
+            (
+                ExportTable::Activities,
+                "user_id,username,day,activity,distance,score,days_late\nu1,tomte,3,RUN,5.5,11.0,1\n",
+            ),
+            (
+                ExportTable::Achievements,
+                "user_id,username,title,rank\nu1,tomte,\"First steps, again\",BRONZE\n",
+            ),
+            (
+                ExportTable::Leaderboard,
+                "rank,username,points,activity_points,achievement_points,bike_dst,run_dst,walk_dst,ski_dst,climb_time,achievements\n\
+                 1,tomte,13.0,11.0,2.0,0.0,5.5,0.0,0.0,0.0,1\n",
+            ),
+        ];
+        for (table, expected) in cases {
+            assert_eq!(export.to_csv(table).unwrap(), expected, "{:?}", table);
+        }
+    }
+
+    #[test]
+    fn json_shape() {
+        let json = serde_json::to_value(export()).unwrap();
+        let keys = |value: &serde_json::Value| {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(
+            keys(&json),
+            vec![
+                "achievements",
+                "activities",
+                "exported_at",
+                "leaderboard",
+                "schema_version",
+                "users"
+            ]
+        );
+        assert_eq!(json["schema_version"], 1);
+        for table in ExportTable::all() {
+            assert!(json[table.name()].is_array(), "{}", table.name());
+        }
+        assert_eq!(keys(&json["users"][0]), vec!["id", "timezone", "username"]);
+        assert_eq!(json["activities"][0]["activity"], "RUN");
+        assert_eq!(json["achievements"][0]["rank"], "BRONZE");
+        assert_eq!(json["leaderboard"][0]["rank"], 1);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_derive::{Deserialize, Serialize};
+
+// Standing of a user in the leaderboard at the end of a day
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub day: i32,
+    pub username: String,
+    pub points: f64,
+    pub rank: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct UserHistory {
+    pub username: String,
+    // One entry per day in `LeaderboardHistory::days`, None if the user had not joined yet
+    pub ranks: Vec<Option<usize>>,
+    pub points: Vec<Option<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Climber {
+    pub day: i32,
+    pub username: String,
+    pub from_rank: usize,
+    pub to_rank: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LeaderboardHistory {
+    pub days: Vec<i32>,
+    pub users: Vec<UserHistory>,
+    // Biggest climber of every day that someone climbed
+    pub climbers: Vec<Climber>,
+}
+
+pub fn leaderboard_history(snapshots: &[Snapshot]) -> LeaderboardHistory {
+    let days: Vec<i32> = snapshots
+        .iter()
+        .map(|s| s.day)
+        .collect::<BTreeSet<i32>>()
+        .into_iter()
+        .collect();
+    let mut per_user: BTreeMap<&str, BTreeMap<i32, &Snapshot>> = BTreeMap::new();
+    for snapshot in snapshots {
+        per_user
+            .entry(&snapshot.username)
+            .or_default()
+            .insert(snapshot.day, snapshot);
+    }
+
+    let users = per_user
+        .iter()
+        .map(|(username, by_day)| UserHistory {
+            username: username.to_string(),
+            ranks: days.iter().map(|d| by_day.get(d).map(|s| s.rank)).collect(),
+            points: days
+                .iter()
+                .map(|d| by_day.get(d).map(|s| s.points))
+                .collect(),
+        })
+        .collect();
+
+    let mut climbers = Vec::new();
+    for pair in days.windows(2) {
+        let (before, after) = (pair[0], pair[1]);
+        let best = per_user
+            .iter()
+            .filter_map(|(username, by_day)| {
+                let from = by_day.get(&before)?.rank;
+                let to = by_day.get(&after)?.rank;
+                if to < from {
+                    Some(Climber {
+                        day: after,
+                        username: username.to_string(),
+                        from_rank: from,
+                        to_rank: to,
+                    })
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|c| c.from_rank - c.to_rank);
+        climbers.extend(best);
+    }
+
+    LeaderboardHistory {
+        days,
+        users,
+        climbers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(day: i32, username: &str, points: f64, rank: usize) -> Snapshot {
+        Snapshot {
+            day,
+            username: username.to_string(),
+            points,
+            rank,
+        }
+    }
+
+    #[test]
+    fn rank_over_time_and_climbers() {
+        let history = leaderboard_history(&[
+            snapshot(0, "anna", 10.0, 1),
+            snapshot(0, "bert", 5.0, 2),
+            snapshot(0, "cecilia", 2.0, 3),
+            snapshot(1, "anna", 12.0, 2),
+            snapshot(1, "bert", 5.0, 3),
+            snapshot(1, "cecilia", 14.0, 1),
+            snapshot(2, "anna", 20.0, 1),
+            snapshot(2, "bert", 9.0, 3),
+            snapshot(2, "cecilia", 14.0, 2),
+            snapshot(2, "david", 1.0, 4),
+        ]);
+
+        assert_eq!(history.days, vec![0, 1, 2]);
+        assert_eq!(history.users[0].ranks, vec![Some(1), Some(2), Some(1)]);
+        assert_eq!(history.users[3].username, "david");
+        assert_eq!(history.users[3].ranks, vec![None, None, Some(4)]);
+        assert_eq!(history.users[3].points, vec![None, None, Some(1.0)]);
+        assert_eq!(
+            history.climbers,
+            vec![
+                Climber {
+                    day: 1,
+                    username: "cecilia".to_string(),
+                    from_rank: 3,
+                    to_rank: 1,
+                },
+                Climber {
+                    day: 2,
+                    username: "anna".to_string(),
+                    from_rank: 2,
+                    to_rank: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_climbers_without_movement() {
+        let history =
+            leaderboard_history(&[snapshot(0, "anna", 10.0, 1), snapshot(1, "anna", 12.0, 1)]);
+        assert!(history.climbers.is_empty());
+        assert!(leaderboard_history(&[]).days.is_empty());
+    }
+}
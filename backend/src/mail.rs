@@ -0,0 +1,168 @@
+use std::io::Write;
+
+use anyhow::Result;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, Message, SmtpTransport, Transport};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer {
+    fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "kind")]
+pub enum MailConfig {
+    // Mails are only printed, for development
+    #[default]
+    Log,
+    // Mails are appended to the file, one json object per line
+    File {
+        path: String,
+    },
+    Smtp {
+        host: String,
+        // Defaults to the submission port with TLS
+        port: Option<u16>,
+        username: String,
+        password: String,
+        from: String,
+    },
+}
+
+pub fn mailer(config: &MailConfig) -> Box<dyn Mailer> {
+    match config {
+        MailConfig::Log => Box::new(LogMailer),
+        MailConfig::File { path } => Box::new(FileMailer {
+            path: path.to_string(),
+        }),
+        MailConfig::Smtp {
+            host,
+            port,
+            username,
+            password,
+            from,
+        } => Box::new(SmtpMailer {
+            host: host.to_string(),
+            port: *port,
+            credentials: Credentials::new(username.to_string(), password.to_string()),
+            from: from.to_string(),
+        }),
+    }
+}
+
+// Normalized form of a mail address, or an error if it is not one
+pub fn parse_address(address: &str) -> Result<String> {
+    let parsed: Address = address
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid email address: {}", address))?;
+    Ok(parsed.to_string())
+}
+
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        println!("Mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+pub struct FileMailer {
+    pub path: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(mail)?)?;
+        Ok(())
+    }
+}
+
+pub struct SmtpMailer {
+    host: String,
+    port: Option<u16>,
+    credentials: Credentials,
+    from: String,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.parse()?)
+            .to(mail.to.parse()?)
+            .subject(&mail.subject)
+            .body(mail.body.clone())?;
+        let mut transport = SmtpTransport::relay(&self.host)?.credentials(self.credentials.clone());
+        if let Some(port) = self.port {
+            transport = transport.port(port);
+        }
+        transport.build().send(&message)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+
+    use super::*;
+
+    // Mails written by a `FileMailer`, oldest first
+    fn read_mails(path: &Path) -> Result<Vec<Mail>> {
+        let file = std::fs::File::open(path)?;
+        let mut mails = Vec::new();
+        for line in BufReader::new(file).lines() {
+            mails.push(serde_json::from_str(&line?)?);
+        }
+        Ok(mails)
+    }
+
+    #[test]
+    fn file_mailer_appends() {
+        let path = std::env::temp_dir().join(format!("mails-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mailer = mailer(&MailConfig::File {
+            path: path.to_string_lossy().to_string(),
+        });
+        let mails = vec![
+            Mail {
+                to: "anna@example.com".to_string(),
+                subject: "First".to_string(),
+                body: "Line one\nLine two".to_string(),
+            },
+            Mail {
+                to: "bert@example.com".to_string(),
+                subject: "Second".to_string(),
+                body: String::new(),
+            },
+        ];
+        for mail in &mails {
+            mailer.send(mail).unwrap();
+        }
+        assert_eq!(read_mails(&path).unwrap(), mails);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            parse_address(" anna@example.com ").unwrap(),
+            "anna@example.com"
+        );
+        assert!(parse_address("anna").is_err());
+        assert!(parse_address("").is_err());
+    }
+}
@@ -1,18 +1,47 @@
 mod achievements;
+mod auth;
+mod backup;
+mod config;
+mod cors;
 mod db;
+mod doors;
+mod evaluator;
+mod export;
+mod history;
+mod mail;
+mod middleware;
+mod moderation;
+mod oidc;
+mod ratelimit;
+mod recompute;
+mod router;
+mod scoring;
+mod static_files;
+mod stats;
+mod tls;
+mod validation;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
 use db::Db;
+use export::ExportTable;
 use hyper::{
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Body, Method, Request, Server, StatusCode,
 };
+use mail::Mail;
+use ratelimit::limiter;
+use router::{handler, wrap, Context, Handler, Response, Router};
 use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value;
 use uuid::Uuid;
-
-type Response = hyper::Response<hyper::Body>;
+use validation::{parse_json, ClientError, ValidationError};
 
 fn ok_json(data: Value) -> Result<Response> {
     Ok(Response::new(Body::from(serde_json::to_vec(&data)?)))
@@ -34,25 +63,147 @@ async fn reg_login(body: Body, add_user: bool) -> Result<Response> {
     struct RegLoginData {
         username: String,
         pass: String,
+        timezone: Option<String>,
+        email: Option<String>,
     }
 
     let bytes = hyper::body::to_bytes(body).await?;
-    let data: RegLoginData = serde_json::from_slice(&bytes)?;
+    let data: RegLoginData = parse_json(&bytes)?;
     let db = Db::new()?;
     if add_user {
-        db.add_user(&data.username, &data.pass)?;
+        db.add_user(
+            &data.username,
+            &data.pass,
+            data.timezone.as_deref(),
+            data.email.as_deref(),
+        )?;
     }
     let user = db.get_user_id(&data.username, &data.pass)?;
     let key = db.get_session_key(user, add_user)?;
-    Ok(ok_json(json!({ "session_key": key }))?)
+    session_response(&db, key)
 }
 
-fn wrong_method(req: Request<hyper::Body>) -> Result<Response> {
-    Ok(nok_reason(format!(
-        "Wrong method: {} for path {}",
-        req.method(),
-        req.uri().path()
-    )))
+// Mails a reset token if the user exists, the response is the same either way to not reveal who
+// is registered
+async fn request_password_reset(body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct ResetRequestData {
+        username: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: ResetRequestData = parse_json(&bytes)?;
+    let db = Db::new()?;
+    if let Some((email, token)) = db.create_reset_token(&data.username)? {
+        let mail = Mail {
+            to: email,
+            subject: "Advent of gah password reset".to_string(),
+            body: format!(
+                "Use this token to set a new password, it is valid for {} minutes:\n\n{}\n",
+                config::config().reset_token_minutes,
+                token
+            ),
+        };
+        // Sent in the background, answering right away also keeps the time it takes from telling
+        // whether the user exists
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = mail::mailer(&config::config().mail).send(&mail) {
+                println!("Failed to send password reset mail: {}", e);
+            }
+        });
+    }
+    ok_json(json!({ "requested": true }))
+}
+
+async fn reset_password(body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct ResetData {
+        token: String,
+        pass: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: ResetData = parse_json(&bytes)?;
+    let db = Db::new()?;
+    let key = db.reset_password(&data.token, &data.pass)?;
+    session_response(&db, key)
+}
+
+fn session_response(db: &Db, key: String) -> Result<Response> {
+    ok_json(json!({ "session_key": auth::issue_token(db, key)? }))
+}
+
+fn redirect(location: &str) -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::FOUND)
+        .header(hyper::header::LOCATION, location)
+        .body(Body::empty())?)
+}
+
+async fn oidc_login() -> Result<Response> {
+    let Some(oidc) = oidc::oidc() else {
+        return Ok(nok_reason("Single sign-on is not configured".to_string()));
+    };
+    redirect(&oidc.authorization_url(None).await?)
+}
+
+// Links an identity of the provider to the logged in user, the browser is sent to the returned
+// url to log in there
+async fn oidc_link(user: Uuid) -> Result<Response> {
+    let Some(oidc) = oidc::oidc() else {
+        return Ok(nok_reason("Single sign-on is not configured".to_string()));
+    };
+    ok_json(json!({ "url": oidc.authorization_url(Some(user)).await? }))
+}
+
+// Logs in or links the user of the identity provider's callback, giving the fragment with the
+// session for the frontend
+async fn oidc_session(oidc: &oidc::Oidc, req: &Request<hyper::Body>) -> Result<String> {
+    let params = query_params(req);
+    if let Some(error) = params.get("error") {
+        return Err(ClientError::bad_request(&format!(
+            "Single sign-on failed: {}",
+            error
+        )));
+    }
+    let (Some(code), Some(state)) = (params.get("code"), params.get("state")) else {
+        return Err(ClientError::bad_request(
+            "Single sign-on callback without code",
+        ));
+    };
+    let (claims, link) = oidc.finish(code, state).await?;
+
+    let db = Db::new()?;
+    let user = db.oidc_user(&oidc.config().issuer, &claims, link)?;
+    let key = db.get_session_key(user, true)?;
+    let mut fragment = url::form_urlencoded::Serializer::new(String::new());
+    fragment
+        .append_pair("session_key", &auth::issue_token(&db, key)?)
+        .append_pair("username", &db.get_user_name(&user.to_string())?);
+    Ok(fragment.finish())
+}
+
+// The identity provider sends the browser here, which is then sent on to the frontend with the
+// session key in the fragment so it never reaches any server logs. Failures are sent there too,
+// as `error` in the fragment
+async fn oidc_callback(req: Request<hyper::Body>) -> Result<Response> {
+    let Some(oidc) = oidc::oidc() else {
+        return Ok(nok_reason("Single sign-on is not configured".to_string()));
+    };
+    let fragment = match oidc_session(oidc, &req).await {
+        Ok(fragment) => fragment,
+        Err(e) => {
+            println!("Single sign-on failed: {:?}", e);
+            let reason = match e.downcast_ref::<ClientError>() {
+                Some(error) => error.reason.as_str(),
+                None => "Single sign-on failed, please try again later",
+            };
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("error", reason)
+                .finish()
+        }
+    };
+    redirect(&format!("{}#{}", oidc.config().frontend_url, fragment))
 }
 
 async fn add_activity(db: Db, user: Uuid, body: Body) -> Result<Response> {
@@ -64,85 +215,534 @@ async fn add_activity(db: Db, user: Uuid, body: Body) -> Result<Response> {
     }
 
     let bytes = hyper::body::to_bytes(body).await?;
-    let data: ActivityPutData = serde_json::from_slice(&bytes)?;
+    let data: ActivityPutData = parse_json(&bytes)?;
     ok_string(serde_json::to_string(
         &db.add_activity(user, data.day, data.info)?,
     )?)
 }
 
-fn unknown_path(req: Request<hyper::Body>) -> Result<Response> {
-    Ok(nok_reason(format!("Unknown path: {}", req.uri().path())))
+async fn set_timezone(db: Db, user: Uuid, body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct TimezonePutData {
+        timezone: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: TimezonePutData = parse_json(&bytes)?;
+    db.set_user_timezone(user, &data.timezone)?;
+    ok_json(json!({ "timezone": data.timezone }))
 }
 
-async fn handle_request(req: Request<hyper::Body>) -> Result<Response> {
-    if let Some(auth) = req.headers().get("Authentification") {
-        let db = Db::new()?;
-        let user = db.get_user_from_session(auth.to_str()?.into())?;
-        match req.method().to_owned() {
-            Method::PUT => match req.uri().path() {
-                "/api/log-activity" => add_activity(db, user, req.into_body()).await,
-                _ => unknown_path(req),
-            },
-            Method::GET => match req.uri().path() {
-                "/api/leaderboard" => {
-                    let lb = db.get_leaderboard()?;
-                    ok_string(serde_json::to_string(&lb)?)
-                }
-                "/api/achievements" => {
-                    let a = db.get_acheivements(user)?;
-                    ok_string(serde_json::to_string(&a)?)
-                }
-                "/api/calendar" => {
-                    let aa = db.get_available_activities()?;
-                    let la = db.get_logged_activities(user)?;
-                    let data = json!({"available_activities" : aa, "logged_activities" : la});
-                    ok_json(data)
-                }
-                "/api/today" => ok_json(json!({ "day": db::today() })),
-                _ => unknown_path(req),
-            },
-            _ => Ok(nok_reason(format!("Method {} not allowed", req.method()))),
+async fn change_password(db: Db, user: Uuid, body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct PasswordPutData {
+        old_pass: String,
+        new_pass: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: PasswordPutData = parse_json(&bytes)?;
+    let key = db.change_password(user, &data.old_pass, &data.new_pass)?;
+    session_response(&db, key)
+}
+
+async fn set_email(db: Db, user: Uuid, body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct EmailPutData {
+        email: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: EmailPutData = parse_json(&bytes)?;
+    ok_json(json!({ "email": db.set_user_email(user, &data.email)? }))
+}
+
+async fn rename_user(db: Db, user: Uuid, body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct UsernamePutData {
+        username: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: UsernamePutData = parse_json(&bytes)?;
+    db.rename_user(user, &data.username)?;
+    ok_json(json!({ "username": data.username }))
+}
+
+fn forbidden() -> Result<Response> {
+    Err(ClientError::forbidden("Only admins can do this"))
+}
+
+async fn delete_user(db: Db, user: Uuid, body: Body) -> Result<Response> {
+    #[derive(Deserialize, Debug)]
+    struct DeleteUserData {
+        // Not needed by users of single sign-on
+        #[serde(default)]
+        pass: String,
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let data: DeleteUserData = parse_json(&bytes)?;
+    db.delete_user(user, &data.pass)?;
+    ok_json(json!({ "deleted": true }))
+}
+
+fn query_params(req: &Request<hyper::Body>) -> HashMap<String, String> {
+    url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn leaderboard_filter(req: &Request<hyper::Body>) -> Result<db::LeaderboardFilter> {
+    let params = query_params(req);
+    let day = |key: &str| -> Result<Option<i32>> {
+        match params.get(key) {
+            Some(day) => {
+                Ok(Some(day.parse().map_err(|_| {
+                    ValidationError::field(key, "Must be a number")
+                })?))
+            }
+            None => Ok(None),
         }
-    } else {
-        match req.uri().path() {
-            "/api/register-user" => match req.method().to_owned() {
-                Method::PUT => reg_login(req.into_body(), true).await,
-                _ => wrong_method(req),
-            },
-            "/api/login" => match req.method().to_owned() {
-                Method::POST => reg_login(req.into_body(), false).await,
-                _ => wrong_method(req),
-            },
-            _ => unknown_path(req),
+    };
+    let activity = match params.get("activity") {
+        Some(activity) => Some(
+            serde_json::from_value(Value::String(activity.to_uppercase()))
+                .map_err(|_| ValidationError::field("activity", "Unknown activity"))?,
+        ),
+        None => None,
+    };
+    let single_day = day("day")?;
+    Ok(db::LeaderboardFilter {
+        activity,
+        from: single_day.or(day("from")?),
+        to: single_day.or(day("to")?),
+    })
+}
+
+// Holds every user's data, so only admins can export it
+fn export(db: &Db, user: Uuid, req: &Request<hyper::Body>) -> Result<Response> {
+    if !db.is_admin(user)? {
+        return forbidden();
+    }
+    let params = query_params(req);
+    let export = db.export()?;
+    match params.get("format").map(|f| f.as_str()) {
+        None | Some("json") => ok_string(serde_json::to_string(&export)?),
+        Some("csv") => {
+            let table: ExportTable = serde_json::from_value(Value::String(
+                params
+                    .get("table")
+                    .ok_or_else(|| ValidationError::field("table", "CSV export needs a table"))?
+                    .to_string(),
+            ))
+            .map_err(|_| ValidationError::field("table", "Unknown table"))?;
+            let mut response = ok_string(export.to_csv(table)?)?;
+            response
+                .headers_mut()
+                .insert("Content-Type", "text/csv".parse()?);
+            Ok(response)
         }
+        Some(format) => Ok(nok_reason(format!("Unknown export format: {}", format))),
     }
 }
 
-async fn try_handle_request(req: Request<hyper::Body>) -> Result<Response> {
-    println!("Got request {:?}", req);
-    match handle_request(req).await {
-        Ok(resp) => {
-            println!("Sending response {:?}", resp);
-            Ok(resp)
-        }
-        Err(e) => {
-            println!("{:?}", e);
-            Err(e)
+// Answers with the remaining queue
+async fn moderate_activity(ctx: Context) -> Result<Response> {
+    let admin = ctx.user()?;
+    let user_id = Uuid::from_str(ctx.param("user_id")?)
+        .map_err(|_| ValidationError::field("user_id", "Not a valid user id"))?;
+    let day: i32 = ctx
+        .param("day")?
+        .parse()
+        .map_err(|_| ValidationError::field("day", "Must be a number"))?;
+    let bytes = hyper::body::to_bytes(ctx.into_body()).await?;
+    let decision: moderation::Decision = parse_json(&bytes)?;
+    let db = Db::new()?;
+    if !db.is_admin(admin)? {
+        return forbidden();
+    }
+    db.moderate_activity(user_id, day, &decision)?;
+    ok_string(serde_json::to_string(&db.get_flagged_activities()?)?)
+}
+
+// Routes that can be used to guess passwords or spam registrations
+fn limited_routes() -> Router {
+    Router::new()
+        .route(
+            Method::PUT,
+            "/api/register-user",
+            handler(|ctx: Context| reg_login(ctx.into_body(), true)),
+        )
+        .route(
+            Method::POST,
+            "/api/login",
+            handler(|ctx: Context| reg_login(ctx.into_body(), false)),
+        )
+        .route(
+            Method::POST,
+            "/api/password-reset",
+            handler(|ctx: Context| request_password_reset(ctx.into_body())),
+        )
+        .route(
+            Method::PUT,
+            "/api/password-reset",
+            handler(|ctx: Context| reset_password(ctx.into_body())),
+        )
+        .route(
+            Method::GET,
+            "/api/oidc/callback",
+            handler(|ctx: Context| oidc_callback(ctx.req)),
+        )
+        .layer(middleware::rate_limit())
+}
+
+fn public_routes() -> Router {
+    Router::new().route(
+        Method::GET,
+        "/api/oidc/login",
+        handler(|_: Context| oidc_login()),
+    )
+}
+
+// Routes that change the account or expose personal data, see `middleware::sensitive_auth`
+fn sensitive_routes() -> Router {
+    Router::new()
+        .route(
+            Method::PUT,
+            "/api/me/password",
+            handler(|ctx: Context| async move {
+                change_password(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::PUT,
+            "/api/me/email",
+            handler(|ctx: Context| async move {
+                set_email(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::PUT,
+            "/api/me/username",
+            handler(|ctx: Context| async move {
+                rename_user(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/me/export",
+            handler(|ctx: Context| async move {
+                ok_string(serde_json::to_string(
+                    &Db::new()?.export_user_data(ctx.user()?)?,
+                )?)
+            }),
+        )
+        .route(
+            Method::DELETE,
+            "/api/me",
+            handler(|ctx: Context| async move {
+                delete_user(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::POST,
+            "/api/oidc/link",
+            handler(|ctx: Context| async move { oidc_link(ctx.user()?).await }),
+        )
+        .route(
+            Method::PUT,
+            "/api/admin/moderation/:user_id/:day",
+            handler(moderate_activity),
+        )
+        .route(
+            Method::GET,
+            "/api/admin/moderation",
+            handler(|ctx: Context| async move {
+                let db = Db::new()?;
+                if !db.is_admin(ctx.user()?)? {
+                    return forbidden();
+                }
+                ok_string(serde_json::to_string(&db.get_flagged_activities()?)?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/export",
+            handler(|ctx: Context| async move { export(&Db::new()?, ctx.user()?, &ctx.req) }),
+        )
+        .route(
+            Method::GET,
+            "/api/metrics/rate-limit",
+            handler(|ctx: Context| async move {
+                if !Db::new()?.is_admin(ctx.user()?)? {
+                    return forbidden();
+                }
+                ok_string(serde_json::to_string(&limiter().lock().unwrap().metrics())?)
+            }),
+        )
+        .layer(middleware::sensitive_auth())
+}
+
+fn protected_routes() -> Router {
+    Router::new()
+        .route(
+            Method::PUT,
+            "/api/log-activity",
+            handler(|ctx: Context| async move {
+                add_activity(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::PUT,
+            "/api/timezone",
+            handler(|ctx: Context| async move {
+                set_timezone(Db::new()?, ctx.user()?, ctx.into_body()).await
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/leaderboard",
+            handler(|ctx: Context| async move {
+                let lb = Db::new()?.get_leaderboard(&leaderboard_filter(&ctx.req)?)?;
+                ok_string(serde_json::to_string(&lb)?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/leaderboard/history",
+            handler(|_: Context| async move {
+                ok_string(serde_json::to_string(
+                    &Db::new()?.get_leaderboard_history()?,
+                )?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/achievements",
+            handler(|ctx: Context| async move {
+                let a = Db::new()?.get_acheivements(ctx.user()?)?;
+                ok_string(serde_json::to_string(&a)?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/calendar",
+            handler(|ctx: Context| async move {
+                let db = Db::new()?;
+                let aa = db.get_available_activities(ctx.user()?)?;
+                let la = db.get_logged_activities(ctx.user()?)?;
+                let data = json!({"available_activities" : aa, "logged_activities" : la});
+                ok_json(data)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/doors",
+            handler(|ctx: Context| async move {
+                ok_string(serde_json::to_string(
+                    &Db::new()?.get_opened_doors(ctx.user()?)?,
+                )?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/me/stats",
+            handler(|ctx: Context| async move {
+                ok_string(serde_json::to_string(
+                    &Db::new()?.get_user_stats(ctx.user()?)?,
+                )?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/score-history",
+            handler(|ctx: Context| async move {
+                ok_string(serde_json::to_string(
+                    &Db::new()?.get_score_history(ctx.user()?)?,
+                )?)
+            }),
+        )
+        .route(
+            Method::GET,
+            "/api/today",
+            handler(|ctx: Context| async move {
+                let db = Db::new()?;
+                ok_json(json!({ "day": db::today(db.get_user_timezone(ctx.user()?)?) }))
+            }),
+        )
+        .layer(middleware::auth())
+}
+
+// Every request passes the middleware in order before it is routed
+fn app() -> Handler {
+    let mut router = limited_routes()
+        .merge(public_routes())
+        .merge(sensitive_routes())
+        .merge(protected_routes());
+    if let Some(dir) = &config::config().frontend_dir {
+        router = router.fallback(handler(|ctx: Context| {
+            static_files::serve(std::path::Path::new(dir), ctx)
+        }));
+    }
+    wrap(
+        router.into_handler(),
+        &[
+            middleware::log(),
+            middleware::cors(config::config().cors.clone()),
+            middleware::internal_errors(),
+            middleware::validation_errors(),
+            middleware::client_errors(),
+            middleware::body_limit(config::config().max_body_bytes),
+        ],
+    )
+}
+
+fn recompute(commit: bool) -> Result<()> {
+    let db = Db::new()?;
+    let report = db.recompute_scores(commit)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !commit {
+        println!("Dry run, use `recompute --commit` to store the new scores");
+    }
+    Ok(())
+}
+
+// Writes a single JSON document, or one CSV file per table into the given directory
+fn export_to(format: &str, path: &str) -> Result<()> {
+    let export = Db::new()?.export()?;
+    match format {
+        "json" => std::fs::write(path, serde_json::to_string_pretty(&export)?)?,
+        "csv" => {
+            std::fs::create_dir_all(path)?;
+            for table in ExportTable::all() {
+                let file = std::path::Path::new(path).join(format!("{}.csv", table.name()));
+                std::fs::write(file, export.to_csv(table)?)?;
+            }
         }
+        _ => return Err(anyhow::anyhow!("Unknown export format: {}", format)),
     }
+    println!("Exported {} to {}", format, path);
+    Ok(())
+}
+
+async fn serve_http(addr: SocketAddr, app: Handler) {
+    let new_service = make_service_fn(move |conn: &AddrStream| {
+        let ip = conn.remote_addr().ip();
+        let app = app.clone();
+        async move { Ok::<_, anyhow::Error>(service_fn(move |req| app(Context::new(req, ip)))) }
+    });
+    let server = Server::bind(&addr).serve(new_service);
+    println!("Listening on http://{}", addr);
+    let _ = server.await;
 }
 
 #[tokio::main]
 async fn main() {
-    let db = Db::new().expect("Db craete failed");
+    config::config();
+    let mut db = Db::new().expect("Db craete failed");
     db.init().expect("Db init failed");
 
-    let new_service = make_service_fn(move |_| async {
-        Ok::<_, anyhow::Error>(service_fn(move |req| try_handle_request(req)))
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        None => {}
+        Some("recompute") => {
+            recompute(args.get(2).map(|a| a.as_str()) == Some("--commit"))
+                .expect("Recompute failed");
+            return;
+        }
+        Some("export") => {
+            match (args.get(2), args.get(3)) {
+                (Some(format), Some(path)) => export_to(format, path).expect("Export failed"),
+                _ => println!("Usage: backend export json <file> | backend export csv <dir>"),
+            }
+            return;
+        }
+        Some("backup") => {
+            match args.get(2) {
+                Some(path) => {
+                    db.backup_to(std::path::Path::new(path))
+                        .expect("Backup failed");
+                    println!("Backed up to {}", path);
+                }
+                None => println!("Usage: backend backup <file>"),
+            }
+            return;
+        }
+        Some("restore") => {
+            match args.get(2) {
+                Some(path) => {
+                    let previous = format!(
+                        "db-before-restore-{}.sqlite",
+                        chrono::Utc::now().timestamp()
+                    );
+                    db.restore_from(std::path::Path::new(path), std::path::Path::new(&previous))
+                        .expect("Restore failed");
+                    println!(
+                        "Restored {}, the previous database was saved to {}",
+                        path, previous
+                    );
+                }
+                None => println!("Usage: backend restore <file>"),
+            }
+            return;
+        }
+        Some(command) => {
+            eprintln!("Unknown command: {}", command);
+            std::process::exit(1);
+        }
+    }
+
+    tokio::spawn(async {
+        loop {
+            let snapshot = tokio::task::spawn_blocking(|| {
+                Db::new().and_then(|db| db.snapshot_leaderboard_if_due())
+            });
+            match snapshot.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => println!("Leaderboard snapshot failed: {:?}", e),
+                Err(e) => println!("Leaderboard snapshot failed: {:?}", e),
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        }
     });
 
-    let addr = "0.0.0.0:8080".parse().expect("adr failed");
-    let server = Server::bind(&addr).serve(new_service);
-    println!("Listening on http://{}", addr);
-    let _ = server.await;
+    if let Some(dir) = &config::config().backup.dir {
+        tokio::spawn(async move {
+            let backup = &config::config().backup;
+            loop {
+                let scheduled = tokio::task::spawn_blocking(move || {
+                    Db::new().and_then(|db| backup::scheduled_backup(&db, backup, dir))
+                });
+                match scheduled.await {
+                    Ok(Ok(path)) => println!("Backed up to {}", path.display()),
+                    Ok(Err(e)) => println!("Scheduled backup failed: {:?}", e),
+                    Err(e) => println!("Scheduled backup failed: {:?}", e),
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(backup.interval_hours * 3600))
+                    .await;
+            }
+        });
+    }
+
+    let http_addr: SocketAddr = "0.0.0.0:8080".parse().expect("adr failed");
+    let Some(tls) = &config::config().tls else {
+        serve_http(http_addr, app()).await;
+        return;
+    };
+    let acceptor = Arc::new(RwLock::new(
+        tls::load_acceptor(tls).expect("Loading the certificate failed"),
+    ));
+    #[cfg(unix)]
+    tokio::spawn(tls::reload_on_hangup(tls.clone(), acceptor.clone()));
+    if tls.redirect_http {
+        tokio::spawn(serve_http(
+            http_addr,
+            wrap(tls::redirect(tls), &[middleware::log()]),
+        ));
+    }
+    let https_addr = SocketAddr::from(([0, 0, 0, 0], tls.port));
+    if let Err(e) = tls::serve(https_addr, acceptor, app()).await {
+        println!("HTTPS server failed: {:?}", e);
+    }
 }
@@ -0,0 +1,328 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, StatusCode};
+use serde_json::Value;
+
+use crate::auth;
+use crate::cors::{is_preflight, CorsConfig};
+use crate::db::Db;
+use crate::ratelimit::{limiter, Blocked};
+use crate::router::{middleware, Context, Handler, Middleware, Response};
+use crate::validation::{ClientError, ValidationError};
+
+fn unauthorized() -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(hyper::header::WWW_AUTHENTICATE, "Bearer")
+        .body("Missing or invalid session".into())?)
+}
+
+fn too_many_requests(blocked: Blocked) -> Response {
+    // Rounded up so clients never retry too early
+    let retry_after =
+        blocked.retry_after.as_secs() + u64::from(blocked.retry_after.subsec_nanos() > 0);
+    hyper::Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Retry-After", retry_after)
+        .body(format!("{:?}, retry after {} seconds", blocked.reason, retry_after).into())
+        .unwrap()
+}
+
+fn bad_request(error: &ValidationError) -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(serde_json::to_vec(error)?.into())?)
+}
+
+fn client_error(error: &ClientError) -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(error.status)
+        .body(serde_json::to_vec(error)?.into())?)
+}
+
+fn payload_too_large(max_bytes: usize) -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(format!("Request body larger than {} bytes", max_bytes).into())?)
+}
+
+// Headers to log, with the credentials left out
+fn redacted(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in [hyper::header::AUTHORIZATION.as_str(), auth::LEGACY_HEADER] {
+        if let Some(value) = headers.get_mut(name) {
+            *value = HeaderValue::from_static("<redacted>");
+        }
+    }
+    headers
+}
+
+pub fn log() -> Middleware {
+    middleware(|ctx: Context, next: Handler| async move {
+        println!(
+            "Got request from {} {} {} {:?} {:?}",
+            ctx.ip,
+            ctx.req.method(),
+            ctx.req.uri(),
+            ctx.req.version(),
+            redacted(ctx.req.headers())
+        );
+        let res = next(ctx).await;
+        match &res {
+            Ok(resp) => println!("Sending response {:?}", resp),
+            Err(e) => println!("{:?}", e),
+        }
+        res
+    })
+}
+
+// Answers requests with invalid input with the fields at fault
+pub fn validation_errors() -> Middleware {
+    middleware(|ctx: Context, next: Handler| async move {
+        match next(ctx).await {
+            Err(e) => match e.downcast_ref::<ValidationError>() {
+                Some(error) => bad_request(error),
+                None => Err(e),
+            },
+            res => res,
+        }
+    })
+}
+
+// Answers requests that can not be done with the status and reason
+pub fn client_errors() -> Middleware {
+    middleware(|ctx: Context, next: Handler| async move {
+        match next(ctx).await {
+            Err(e) => match e.downcast_ref::<ClientError>() {
+                Some(error) => client_error(error),
+                None => Err(e),
+            },
+            res => res,
+        }
+    })
+}
+
+// Answers the other errors of handlers with 500 instead of dropping the connection, the details are
+// only logged
+pub fn internal_errors() -> Middleware {
+    middleware(|ctx: Context, next: Handler| async move {
+        match next(ctx).await {
+            Err(e) => {
+                println!("Request failed: {:?}", e);
+                Ok(hyper::Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal server error".into())?)
+            }
+            res => res,
+        }
+    })
+}
+
+// Answers preflights before they are routed and adds the CORS headers for allowed origins
+pub fn cors(config: CorsConfig) -> Middleware {
+    let config = Arc::new(config);
+    middleware(move |ctx: Context, next: Handler| {
+        let config = config.clone();
+        async move {
+            let origin = ctx
+                .req
+                .headers()
+                .get(hyper::header::ORIGIN)
+                .and_then(|o| o.to_str().ok())
+                .map(|o| o.to_string());
+            if is_preflight(ctx.req.method(), ctx.req.headers()) {
+                let origin = origin.unwrap_or_default();
+                let Some(headers) = config.headers(&origin, true) else {
+                    return Ok(hyper::Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(format!("Origin {} is not allowed", origin).into())?);
+                };
+                let mut res = Response::new(Body::empty());
+                *res.status_mut() = StatusCode::NO_CONTENT;
+                res.headers_mut().extend(headers);
+                return Ok(res);
+            }
+            let mut res = next(ctx).await?;
+            if let Some(headers) = origin.and_then(|o| config.headers(&o, false)) {
+                for (name, value) in headers.iter() {
+                    res.headers_mut().append(name, value.clone());
+                }
+            }
+            Ok(res)
+        }
+    })
+}
+
+// Reads the whole body up front, so handlers never buffer more than `max_bytes`
+pub fn body_limit(max_bytes: usize) -> Middleware {
+    middleware(move |mut ctx: Context, next: Handler| async move {
+        let declared = ctx
+            .req
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if declared.is_some_and(|len| len > max_bytes) {
+            return payload_too_large(max_bytes);
+        }
+        let (parts, mut body) = ctx.req.into_parts();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > max_bytes {
+                return payload_too_large(max_bytes);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        ctx.req = Request::from_parts(parts, Body::from(bytes));
+        next(ctx).await
+    })
+}
+
+async fn authenticated(mut ctx: Context, next: Handler, current_session: bool) -> Result<Response> {
+    let Some(token) = auth::bearer_token(ctx.req.headers()) else {
+        return unauthorized();
+    };
+    let Some(user) = auth::authenticate(&Db::new()?, &token, current_session)? else {
+        return unauthorized();
+    };
+    ctx.user = Some(user);
+    next(ctx).await
+}
+
+// Sets the user of the session or signed token, or answers 401
+pub fn auth() -> Middleware {
+    middleware(|ctx: Context, next: Handler| authenticated(ctx, next, false))
+}
+
+// Like `auth`, but signed tokens only work for the session they were issued for, so a stolen one
+// stops working once the password is changed or reset
+pub fn sensitive_auth() -> Middleware {
+    middleware(|ctx: Context, next: Handler| authenticated(ctx, next, true))
+}
+
+// For routes that can be used to guess passwords or spam registrations, failed logins make the
+// user wait longer and longer until the account is locked for a while
+pub fn rate_limit() -> Middleware {
+    middleware(|mut ctx: Context, next: Handler| async move {
+        let (parts, body) = ctx.req.into_parts();
+        let bytes = hyper::body::to_bytes(body).await?;
+        let username = match parts.uri.path() {
+            "/api/login" => serde_json::from_slice::<Value>(&bytes)
+                .ok()
+                .and_then(|v| v["username"].as_str().map(|u| u.to_string())),
+            _ => None,
+        };
+        let checked = limiter()
+            .lock()
+            .unwrap()
+            .check(ctx.ip, username.as_deref(), Instant::now());
+        if let Err(blocked) = checked {
+            return Ok(too_many_requests(blocked));
+        }
+
+        ctx.req = Request::from_parts(parts, Body::from(bytes));
+        let res = next(ctx).await;
+        if let Some(username) = username {
+            let mut limiter = limiter().lock().unwrap();
+            match &res {
+                Ok(resp) if resp.status().is_success() => limiter.record_success(&username),
+                _ => limiter.record_failure(),
+            }
+        }
+        res
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{handler, wrap};
+
+    #[test]
+    fn redacts_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer abc".parse().unwrap());
+        headers.insert("authentification", "abc".parse().unwrap());
+        headers.insert("content-type", "application/json".parse().unwrap());
+        let logged = format!("{:?}", redacted(&headers));
+        assert!(!logged.contains("abc"), "{}", logged);
+        assert!(logged.contains("application/json"), "{}", logged);
+        assert_eq!(headers["authorization"], "Bearer abc");
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let app = wrap(
+            handler(|ctx: Context| async move {
+                match ctx.req.uri().path() {
+                    "/invalid" => Err(ValidationError::field("day", "Must be a number")),
+                    "/taken" => Err(ClientError::conflict("Already registered")),
+                    "/broken" => Err(anyhow::anyhow!("Database is locked")),
+                    _ => Ok(Response::new(Body::from("ok"))),
+                }
+            }),
+            &[internal_errors(), validation_errors(), client_errors()],
+        );
+        let cases = vec![
+            ("/ok", StatusCode::OK, "ok"),
+            (
+                "/invalid",
+                StatusCode::BAD_REQUEST,
+                r#"{"errors":[{"field":"day","message":"Must be a number"}]}"#,
+            ),
+            (
+                "/taken",
+                StatusCode::CONFLICT,
+                r#"{"reason":"Already registered"}"#,
+            ),
+            (
+                "/broken",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error",
+            ),
+        ];
+        for (path, status, body) in cases {
+            let req = Request::get(path).body(Body::empty()).unwrap();
+            let res = app(Context::new(req, "127.0.0.1".parse().unwrap()))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), status, "{}", path);
+            assert_eq!(hyper::body::to_bytes(res).await.unwrap(), body, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn body_limits() {
+        let echo = wrap(
+            handler(|ctx: Context| async move {
+                let bytes = hyper::body::to_bytes(ctx.into_body()).await?;
+                Ok(Response::new(Body::from(bytes)))
+            }),
+            &[body_limit(4)],
+        );
+        let ip = "127.0.0.1".parse().unwrap();
+        let request = |body: Body| Context::new(Request::new(body), ip);
+
+        let res = echo(request(Body::from("abcd"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hyper::body::to_bytes(res).await.unwrap(), "abcd");
+
+        let res = echo(request(Body::from("abcde"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Without a length up front
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for chunk in ["abc", "de"] {
+                let _ = sender.send_data(chunk.into()).await;
+            }
+        });
+        let res = echo(request(body)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::Activity;
+use crate::stats::median;
+
+// Review state of a logged activity
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ModerationStatus {
+    #[default]
+    Ok,
+    // Waiting for an admin, does not count until approved or adjusted
+    Flagged,
+    Approved,
+    // The value was corrected by an admin
+    Adjusted,
+    Rejected,
+}
+
+impl ModerationStatus {
+    // Whether the activity counts towards the leaderboard, statistics and achievements
+    pub fn counts(&self) -> bool {
+        !matches!(self, ModerationStatus::Flagged | ModerationStatus::Rejected)
+    }
+}
+
+// A value per activity type, km or minutes for climbing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActivityLimits {
+    pub bike: f64,
+    pub run: f64,
+    pub walk: f64,
+    pub ski: f64,
+    pub climb: f64,
+}
+
+impl ActivityLimits {
+    pub fn get(&self, activity: Activity) -> f64 {
+        match activity {
+            Activity::BIKE => self.bike,
+            Activity::RUN => self.run,
+            Activity::WALK => self.walk,
+            Activity::SKI => self.ski,
+            Activity::CLIMB => self.climb,
+        }
+    }
+}
+
+// Limits given in the config, the missing ones keep their default
+#[derive(Deserialize)]
+struct PartialLimits {
+    bike: Option<f64>,
+    run: Option<f64>,
+    walk: Option<f64>,
+    ski: Option<f64>,
+    climb: Option<f64>,
+}
+
+impl PartialLimits {
+    fn or(self, defaults: ActivityLimits) -> ActivityLimits {
+        ActivityLimits {
+            bike: self.bike.unwrap_or(defaults.bike),
+            run: self.run.unwrap_or(defaults.run),
+            walk: self.walk.unwrap_or(defaults.walk),
+            ski: self.ski.unwrap_or(defaults.ski),
+            climb: self.climb.unwrap_or(defaults.climb),
+        }
+    }
+}
+
+fn max_limits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ActivityLimits, D::Error> {
+    Ok(PartialLimits::deserialize(deserializer)?.or(PlausibilityConfig::default().max))
+}
+
+fn flag_limits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ActivityLimits, D::Error> {
+    Ok(PartialLimits::deserialize(deserializer)?.or(PlausibilityConfig::default().flag))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PlausibilityConfig {
+    // Larger values are rejected right away
+    #[serde(deserialize_with = "max_limits")]
+    pub max: ActivityLimits,
+    // Larger values are flagged for review
+    #[serde(deserialize_with = "flag_limits")]
+    pub flag: ActivityLimits,
+    // Values this many times the median of the user's earlier activities of the type are flagged
+    pub outlier_factor: f64,
+    // Earlier activities of the type needed before outliers are flagged
+    pub outlier_min_history: usize,
+}
+
+impl Default for PlausibilityConfig {
+    fn default() -> Self {
+        Self {
+            max: ActivityLimits {
+                bike: 500.0,
+                run: 150.0,
+                walk: 100.0,
+                ski: 200.0,
+                climb: 24.0 * 60.0,
+            },
+            flag: ActivityLimits {
+                bike: 150.0,
+                run: 45.0,
+                walk: 40.0,
+                ski: 80.0,
+                climb: 300.0,
+            },
+            outlier_factor: 5.0,
+            outlier_min_history: 3,
+        }
+    }
+}
+
+// Why an activity should be reviewed, `history` holds the user's earlier values of the same type
+pub fn flag_reason(
+    activity: Activity,
+    value: f64,
+    history: &[f64],
+    config: &PlausibilityConfig,
+) -> Option<String> {
+    let limit = config.flag.get(activity);
+    if value > limit {
+        return Some(format!("Above the limit of {} for {:?}", limit, activity));
+    }
+    if history.len() >= config.outlier_min_history {
+        let usual = median(history);
+        if value > usual * config.outlier_factor {
+            return Some(format!(
+                "More than {} times the usual {} for {:?}",
+                config.outlier_factor, usual, activity
+            ));
+        }
+    }
+    None
+}
+
+// What an admin decides for a flagged activity
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action")]
+pub enum Decision {
+    Approve,
+    // Replace the logged value, the score is derived again
+    Adjust { value: f64 },
+    Reject,
+}
+
+// Admins are listed by user id, as anyone can register or rename to a username
+pub fn is_admin(admins: &[String], user: Uuid) -> bool {
+    admins
+        .iter()
+        .any(|admin| Uuid::parse_str(admin.trim()).is_ok_and(|id| id == user))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FlaggedActivity {
+    pub user_id: String,
+    pub username: String,
+    pub day: i32,
+    pub activity: Activity,
+    pub distance: f64,
+    pub score: f64,
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let config = PlausibilityConfig::default();
+        let cases: Vec<(Activity, f64, Vec<f64>, bool)> = vec![
+            (Activity::WALK, 5.0, vec![], false),
+            (Activity::WALK, 40.0, vec![], false),
+            (Activity::WALK, 40.5, vec![], true),
+            // Not enough history to know what is usual
+            (Activity::RUN, 30.0, vec![2.0, 3.0], false),
+            (Activity::RUN, 30.0, vec![2.0, 3.0, 5.0], true),
+            (Activity::RUN, 15.0, vec![2.0, 3.0, 5.0], false),
+            (Activity::CLIMB, 120.0, vec![60.0, 90.0, 45.0], false),
+        ];
+        for (activity, value, history, flagged) in cases {
+            assert_eq!(
+                flag_reason(activity, value, &history, &config).is_some(),
+                flagged,
+                "{:?} {} {:?}",
+                activity,
+                value,
+                history
+            );
+        }
+    }
+
+    #[test]
+    fn partial_limits() {
+        let config: PlausibilityConfig =
+            serde_json::from_str(r#"{"max": {"bike": 300}, "flag": {"climb": 240}}"#).unwrap();
+        let defaults = PlausibilityConfig::default();
+        assert_eq!(
+            config.max,
+            ActivityLimits {
+                bike: 300.0,
+                ..defaults.max
+            }
+        );
+        assert_eq!(
+            config.flag,
+            ActivityLimits {
+                climb: 240.0,
+                ..defaults.flag
+            }
+        );
+        assert_eq!(config.outlier_factor, defaults.outlier_factor);
+    }
+
+    #[test]
+    fn counted_statuses() {
+        assert!(ModerationStatus::Ok.counts());
+        assert!(ModerationStatus::Approved.counts());
+        assert!(ModerationStatus::Adjusted.counts());
+        assert!(!ModerationStatus::Flagged.counts());
+        assert!(!ModerationStatus::Rejected.counts());
+    }
+
+    #[test]
+    fn admins() {
+        let user = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let cases = vec![
+            (vec![], false),
+            (vec!["67e55044-10b1-426f-9247-bb680e5fe0c8"], true),
+            (vec!["67E55044-10B1-426F-9247-BB680E5FE0C8"], true),
+            (
+                vec!["someone", " 67e55044-10b1-426f-9247-bb680e5fe0c8 "],
+                true,
+            ),
+            (vec!["4fd2b1a1-0a57-4b5e-a6b0-1f0e9c6b1c2d"], false),
+            // Usernames never count, even the user's own
+            (vec!["admin"], false),
+        ];
+        for (admins, expected) in cases {
+            let admins: Vec<String> = admins.iter().map(|a| a.to_string()).collect();
+            assert_eq!(is_admin(&admins, user), expected, "{:?}", admins);
+        }
+    }
+}
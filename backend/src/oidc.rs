@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+use uuid::Uuid;
+
+use crate::config::config;
+use crate::validation::{ClientError, USERNAME_MAX_CHARS, USERNAME_MIN_CHARS};
+
+// Logins that are not finished within this time have to start over
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct OidcConfig {
+    // The discovery document is read from `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    // Only for confidential clients, PKCE is used either way
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    // URL of /api/oidc/callback as registered with the identity provider
+    pub redirect_url: String,
+    // Where the browser is sent after logging in, with `#session_key=...&username=...` appended
+    #[serde(default = "default_frontend_url")]
+    pub frontend_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_frontend_url() -> String {
+    "/".to_string()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "profile".to_string(),
+        "email".to_string(),
+    ]
+}
+
+#[derive(Deserialize, Debug)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+// Claims of a verified ID token
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IdClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+    nonce: Option<String>,
+}
+
+struct Pending {
+    verifier: String,
+    nonce: String,
+    started: Instant,
+    // Logged in user that asked to link the identity to their account
+    link: Option<Uuid>,
+}
+
+pub struct Oidc {
+    config: OidcConfig,
+    // Logins sent to the identity provider, by state
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+pub fn oidc() -> Option<&'static Oidc> {
+    static OIDC: OnceLock<Option<Oidc>> = OnceLock::new();
+    OIDC.get_or_init(|| config().oidc.clone().map(Oidc::new))
+        .as_ref()
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+async fn send_json<T: DeserializeOwned>(req: Request<Body>) -> Result<T> {
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let uri = req.uri().clone();
+    let res = client.request(req).await?;
+    let status = res.status();
+    let bytes = hyper::body::to_bytes(res.into_body()).await?;
+    if !status.is_success() {
+        return Err(anyhow::anyhow!(
+            "{} from {}: {}",
+            status,
+            uri,
+            String::from_utf8_lossy(&bytes)
+        ));
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    send_json(
+        Request::get(url)
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())?,
+    )
+    .await
+}
+
+fn verify_id_token(token: &str, jwks: &Jwks, issuer: &str, client_id: &str) -> Result<IdClaims> {
+    let header = decode_header(token)?;
+    if !matches!(
+        header.alg,
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512
+    ) {
+        return Err(anyhow::anyhow!(
+            "Unsupported ID token algorithm {:?}",
+            header.alg
+        ));
+    }
+    let jwk = jwks
+        .keys
+        .iter()
+        .filter(|k| k.kty == "RSA")
+        .find(|k| header.kid.is_none() || k.kid == header.kid)
+        .ok_or_else(|| anyhow::anyhow!("No key to verify the ID token with"))?;
+    let key = match (&jwk.n, &jwk.e) {
+        (Some(n), Some(e)) => DecodingKey::from_rsa_components(n, e)?,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Key of the identity provider is incomplete"
+            ))
+        }
+    };
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    Ok(decode::<IdClaims>(token, &key, &validation)?.claims)
+}
+
+// Username for a user created on first login, before it is made unique
+pub fn username_candidate(claims: &IdClaims) -> String {
+    let source = claims
+        .preferred_username
+        .clone()
+        .or_else(|| claims.name.clone())
+        .or_else(|| {
+            claims
+                .email
+                .as_ref()
+                .and_then(|e| e.split('@').next().map(|local| local.to_string()))
+        })
+        .unwrap_or_default();
+    // Room is left for a number making it unique
+    let name: String = source
+        .chars()
+        .map(|c| if c.is_whitespace() { '.' } else { c })
+        .filter(|c| c.is_alphanumeric() || "_-.".contains(*c))
+        .take(USERNAME_MAX_CHARS - 4)
+        .collect();
+    if name.chars().count() < USERNAME_MIN_CHARS {
+        return "user".to_string();
+    }
+    name
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    async fn discover(&self) -> Result<Discovery> {
+        let issuer = self.config.issuer.trim_end_matches('/');
+        let discovery: Discovery =
+            get_json(&format!("{}/.well-known/openid-configuration", issuer)).await?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            return Err(anyhow::anyhow!(
+                "Identity provider claims to be {}, not {}",
+                discovery.issuer,
+                issuer
+            ));
+        }
+        Ok(discovery)
+    }
+
+    // Starts a login, the browser is sent to the returned URL of the identity provider. With
+    // `link` the identity is linked to that user instead of logging in.
+    pub async fn authorization_url(&self, link: Option<Uuid>) -> Result<String> {
+        let discovery = self.discover().await?;
+        let state = random_string();
+        let nonce = random_string();
+        let verifier = random_string();
+        let mut url = Url::parse(&discovery.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &pkce_challenge(&verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.started.elapsed() < PENDING_TIMEOUT);
+        pending.insert(
+            state,
+            Pending {
+                verifier,
+                nonce,
+                started: Instant::now(),
+                link,
+            },
+        );
+        Ok(url.into())
+    }
+
+    // Finishes the login the identity provider redirected back from with `code` and `state`,
+    // along with the user to link the identity to if any
+    pub async fn finish(&self, code: &str, state: &str) -> Result<(IdClaims, Option<Uuid>)> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|p| p.started.elapsed() < PENDING_TIMEOUT)
+            .ok_or_else(|| {
+                ClientError::bad_request("Unknown or expired login, please try again")
+            })?;
+
+        let discovery = self.discover().await?;
+        let form = {
+            let mut form = url::form_urlencoded::Serializer::new(String::new());
+            form.append_pair("grant_type", "authorization_code")
+                .append_pair("code", code)
+                .append_pair("redirect_uri", &self.config.redirect_url)
+                .append_pair("client_id", &self.config.client_id)
+                .append_pair("code_verifier", &pending.verifier);
+            if let Some(secret) = &self.config.client_secret {
+                form.append_pair("client_secret", secret);
+            }
+            form.finish()
+        };
+        let req = Request::post(&discovery.token_endpoint)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .body(Body::from(form))?;
+        let tokens: TokenResponse = send_json(req).await?;
+
+        let jwks: Jwks = get_json(&discovery.jwks_uri).await?;
+        let claims = verify_id_token(
+            &tokens.id_token,
+            &jwks,
+            &discovery.issuer,
+            &self.config.client_id,
+        )?;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(ClientError::bad_request(
+                "ID token was not issued for this login",
+            ));
+        }
+        Ok((claims, pending.link))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use hyper::server::conn::AddrIncoming;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const CLIENT_ID: &str = "adventofgah";
+    const REDIRECT_URL: &str = "http://localhost:8080/api/oidc/callback";
+
+    // What the mock identity provider remembers between authorize and token requests
+    #[derive(Default)]
+    struct MockState {
+        challenge: String,
+        nonce: String,
+    }
+
+    fn query(uri: &hyper::Uri) -> HashMap<String, String> {
+        url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    async fn mock_idp(
+        req: Request<Body>,
+        issuer: String,
+        state: Arc<Mutex<MockState>>,
+    ) -> Result<Response<Body>, Infallible> {
+        let json = |value: serde_json::Value| Response::new(Body::from(value.to_string()));
+        let res = match req.uri().path() {
+            "/.well-known/openid-configuration" => json(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            })),
+            // Logs everyone in right away
+            "/authorize" => {
+                let params = query(req.uri());
+                assert_eq!(params["client_id"], CLIENT_ID);
+                assert_eq!(params["code_challenge_method"], "S256");
+                let mut state = state.lock().unwrap();
+                state.challenge = params["code_challenge"].clone();
+                state.nonce = params["nonce"].clone();
+                Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(
+                        "Location",
+                        format!(
+                            "{}?code=the%2Fcode&state={}",
+                            params["redirect_uri"], params["state"]
+                        ),
+                    )
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            "/token" => {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let form: HashMap<String, String> =
+                    url::form_urlencoded::parse(&body).into_owned().collect();
+                let state = state.lock().unwrap();
+                if form["code"] != "the/code"
+                    || pkce_challenge(&form["code_verifier"]) != state.challenge
+                {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(r#"{"error":"invalid_grant"}"#))
+                        .unwrap());
+                }
+                let now = chrono::Utc::now().timestamp();
+                let claims = json!({
+                    "iss": issuer,
+                    "aud": CLIENT_ID,
+                    "sub": "1234",
+                    "iat": now,
+                    "exp": now + 300,
+                    "nonce": state.nonce,
+                    "email": "anna@example.com",
+                    "email_verified": true,
+                    "preferred_username": "anna",
+                });
+                let mut header = Header::new(Algorithm::RS256);
+                header.kid = Some("mock".to_string());
+                let key = EncodingKey::from_rsa_pem(include_bytes!("../testdata/mock-idp-key.pem"))
+                    .unwrap();
+                json(json!({
+                    "access_token": "unused",
+                    "token_type": "Bearer",
+                    "id_token": encode(&header, &claims, &key).unwrap(),
+                }))
+            }
+            "/jwks" => json(json!({
+                "keys": [{
+                    "kty": "RSA",
+                    "kid": "mock",
+                    "alg": "RS256",
+                    "n": include_str!("../testdata/mock-idp-key.n"),
+                    "e": "AQAB",
+                }]
+            })),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap(),
+        };
+        Ok(res)
+    }
+
+    async fn start_mock_idp() -> String {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let incoming = AddrIncoming::bind(&addr).unwrap();
+        let issuer = format!("http://{}", incoming.local_addr());
+        let service_issuer = issuer.clone();
+        let make_service = make_service_fn(move |_| {
+            let issuer = service_issuer.clone();
+            let state = state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    mock_idp(req, issuer.clone(), state.clone())
+                }))
+            }
+        });
+        tokio::spawn(Server::builder(incoming).serve(make_service));
+        issuer
+    }
+
+    // Follows the authorization URL like a browser and returns the callback parameters
+    async fn authorize(url: &str) -> HashMap<String, String> {
+        let res = Client::new().get(url.parse().unwrap()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FOUND);
+        let location: hyper::Uri = res.headers()["Location"].to_str().unwrap().parse().unwrap();
+        query(&location)
+    }
+
+    #[tokio::test]
+    async fn login_against_mock_idp() {
+        let issuer = start_mock_idp().await;
+        let oidc = Oidc::new(OidcConfig {
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_url: REDIRECT_URL.to_string(),
+            frontend_url: default_frontend_url(),
+            scopes: default_scopes(),
+        });
+
+        let callback = authorize(&oidc.authorization_url(None).await.unwrap()).await;
+        let (claims, link) = oidc
+            .finish(&callback["code"], &callback["state"])
+            .await
+            .unwrap();
+        assert_eq!(link, None);
+        assert_eq!(claims.sub, "1234");
+        assert_eq!(claims.email.as_deref(), Some("anna@example.com"));
+        assert!(claims.email_verified);
+        assert_eq!(username_candidate(&claims), "anna");
+
+        // A state can only be used once
+        assert!(oidc
+            .finish(&callback["code"], &callback["state"])
+            .await
+            .is_err());
+        assert!(oidc.finish("the/code", "made up").await.is_err());
+
+        let user = Uuid::new_v4();
+        let callback = authorize(&oidc.authorization_url(Some(user)).await.unwrap()).await;
+        let (_, link) = oidc
+            .finish(&callback["code"], &callback["state"])
+            .await
+            .unwrap();
+        assert_eq!(link, Some(user));
+    }
+
+    #[test]
+    fn username_candidates() {
+        let claims = |preferred: Option<&str>, name: Option<&str>, email: Option<&str>| IdClaims {
+            sub: "1".to_string(),
+            email: email.map(|e| e.to_string()),
+            email_verified: false,
+            preferred_username: preferred.map(|p| p.to_string()),
+            name: name.map(|n| n.to_string()),
+            nonce: None,
+        };
+        let cases = vec![
+            (claims(Some("anna"), Some("Anna S"), None), "anna"),
+            (claims(None, Some("Anna Svensson"), None), "Anna.Svensson"),
+            (claims(None, None, Some("bert+gah@example.com")), "bertgah"),
+            (claims(None, None, None), "user"),
+            (claims(Some("🎄"), None, None), "user"),
+        ];
+        for (claims, expected) in cases {
+            assert_eq!(username_candidate(&claims), expected, "{:?}", claims);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::config;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    // Requests to the limited routes allowed per IP within `window_seconds`
+    pub ip_attempts: usize,
+    pub window_seconds: u64,
+    // Wait after a failed login of a user, doubled for every further failure in a row
+    pub backoff_seconds: u64,
+    pub max_backoff_seconds: u64,
+    // Failed logins in a row that lock the account for `lockout_minutes`. Failures are forgotten
+    // after `lockout_minutes` without any.
+    pub lockout_failures: u32,
+    pub lockout_minutes: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            ip_attempts: 20,
+            window_seconds: 60,
+            backoff_seconds: 1,
+            max_backoff_seconds: 30,
+            lockout_failures: 10,
+            lockout_minutes: 15,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum BlockReason {
+    TooManyRequests,
+    Backoff,
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blocked {
+    pub reason: BlockReason,
+    pub retry_after: Duration,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub blocked_ip: u64,
+    pub blocked_backoff: u64,
+    pub blocked_locked: u64,
+    pub failed_logins: u64,
+    pub lockouts: u64,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    // Failed logins in a row since the last success or lockout, including the ones still running
+    count: u32,
+    last: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Limiter {
+    config: RateLimitConfig,
+    ips: HashMap<IpAddr, VecDeque<Instant>>,
+    users: HashMap<String, Failures>,
+    metrics: Metrics,
+}
+
+pub fn limiter() -> &'static Mutex<Limiter> {
+    static LIMITER: OnceLock<Mutex<Limiter>> = OnceLock::new();
+    LIMITER.get_or_init(|| Mutex::new(Limiter::new(config().rate_limit.clone())))
+}
+
+fn user_key(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+impl Limiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            ips: HashMap::new(),
+            users: HashMap::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    // Counts an attempt from `ip` unless it is blocked. A login counts as failed until
+    // `record_success`, so logins running at the same time can not get past the backoff.
+    pub fn check(
+        &mut self,
+        ip: IpAddr,
+        username: Option<&str>,
+        now: Instant,
+    ) -> Result<(), Blocked> {
+        let window = Duration::from_secs(self.config.window_seconds);
+        self.ips.retain(|_, attempts| {
+            while attempts
+                .front()
+                .is_some_and(|first| now.duration_since(*first) >= window)
+            {
+                attempts.pop_front();
+            }
+            !attempts.is_empty()
+        });
+        let forget = Duration::from_secs(self.config.lockout_minutes * 60);
+        self.users.retain(|_, failures| {
+            failures.locked_until.is_some_and(|until| until > now)
+                || failures
+                    .last
+                    .is_some_and(|last| now.duration_since(last) < forget)
+        });
+        let attempts = self.ips.entry(ip).or_default();
+        if attempts.len() >= self.config.ip_attempts {
+            self.metrics.blocked_ip += 1;
+            return Err(Blocked {
+                reason: BlockReason::TooManyRequests,
+                retry_after: window.saturating_sub(now.duration_since(attempts[0])),
+            });
+        }
+        attempts.push_back(now);
+
+        let Some(username) = username else {
+            return Ok(());
+        };
+        if let Some(failures) = self.users.get_mut(&user_key(username)) {
+            if let Some(until) = failures.locked_until {
+                if until > now {
+                    self.metrics.blocked_locked += 1;
+                    return Err(Blocked {
+                        reason: BlockReason::Locked,
+                        retry_after: until - now,
+                    });
+                }
+                failures.locked_until = None;
+            }
+            if let (Some(last), true) = (failures.last, failures.count > 0) {
+                let wait = Duration::from_secs(
+                    self.config
+                        .backoff_seconds
+                        .saturating_mul(1 << (failures.count - 1).min(16))
+                        .min(self.config.max_backoff_seconds),
+                );
+                let allowed = last + wait;
+                if allowed > now {
+                    self.metrics.blocked_backoff += 1;
+                    return Err(Blocked {
+                        reason: BlockReason::Backoff,
+                        retry_after: allowed - now,
+                    });
+                }
+            }
+        }
+        self.reserve(username, now);
+        Ok(())
+    }
+
+    fn reserve(&mut self, username: &str, now: Instant) {
+        let failures = self.users.entry(user_key(username)).or_default();
+        failures.count += 1;
+        failures.last = Some(now);
+        if failures.count >= self.config.lockout_failures {
+            failures.count = 0;
+            failures.locked_until =
+                Some(now + Duration::from_secs(self.config.lockout_minutes * 60));
+            self.metrics.lockouts += 1;
+        }
+    }
+
+    // The login was already counted as failed by `check`
+    pub fn record_failure(&mut self) {
+        self.metrics.failed_logins += 1;
+    }
+
+    // Also lifts a lockout the successful login itself caused
+    pub fn record_success(&mut self, username: &str) {
+        self.users.remove(&user_key(username));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> Limiter {
+        Limiter::new(RateLimitConfig {
+            ip_attempts: 3,
+            window_seconds: 60,
+            backoff_seconds: 1,
+            max_backoff_seconds: 4,
+            lockout_failures: 5,
+            lockout_minutes: 15,
+        })
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn ip_window() {
+        let mut limiter = limiter();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+        for s in 0..3 {
+            assert!(limiter.check(ip, None, start + secs(s)).is_ok());
+        }
+        let blocked = limiter.check(ip, None, start + secs(10)).unwrap_err();
+        assert_eq!(blocked.reason, BlockReason::TooManyRequests);
+        assert_eq!(blocked.retry_after, secs(50));
+        assert!(limiter.check(other, None, start + secs(10)).is_ok());
+        assert!(limiter.check(ip, None, start + secs(60)).is_ok());
+        assert_eq!(limiter.metrics().blocked_ip, 1);
+    }
+
+    #[test]
+    fn backoff_and_lockout() {
+        let mut limiter = limiter();
+        let start = Instant::now();
+        let mut now = start;
+        // Every attempt comes from a new IP to only test the per user limits
+        let mut ip = 0u32;
+        let mut check = |limiter: &mut Limiter, user: &str, now: Instant| {
+            ip += 1;
+            limiter.check(IpAddr::from(ip.to_be_bytes()), Some(user), now)
+        };
+
+        // Waits of 1, 2, 4 and then capped at 4 seconds
+        for wait in [1, 2, 4, 4] {
+            assert!(check(&mut limiter, "Anna", now).is_ok());
+            limiter.record_failure();
+            let blocked = check(&mut limiter, "anna", now).unwrap_err();
+            assert_eq!(blocked.reason, BlockReason::Backoff);
+            assert_eq!(blocked.retry_after, secs(wait));
+            assert!(check(&mut limiter, "bert", now).is_ok());
+            now += secs(wait);
+        }
+
+        assert!(check(&mut limiter, "anna", now).is_ok());
+        limiter.record_failure();
+        let blocked = check(&mut limiter, "anna", now + secs(60)).unwrap_err();
+        assert_eq!(blocked.reason, BlockReason::Locked);
+        assert_eq!(blocked.retry_after, secs(14 * 60));
+
+        now += secs(15 * 60);
+        assert!(check(&mut limiter, "anna", now).is_ok());
+        limiter.record_failure();
+        assert!(check(&mut limiter, "anna", now + secs(1)).is_ok());
+        limiter.record_success("anna");
+        assert!(check(&mut limiter, "anna", now + secs(1)).is_ok());
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.failed_logins, 6);
+        assert_eq!(metrics.lockouts, 1);
+        assert_eq!(metrics.blocked_backoff, 4);
+        assert_eq!(metrics.blocked_locked, 1);
+    }
+
+    #[test]
+    fn concurrent_logins() {
+        let mut limiter = limiter();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+        // The first login has not finished, yet the second one already waits for it
+        assert!(limiter.check(ip, Some("anna"), now).is_ok());
+        let blocked = limiter.check(other, Some("anna"), now).unwrap_err();
+        assert_eq!(blocked.reason, BlockReason::Backoff);
+        limiter.record_success("anna");
+        assert!(limiter.check(other, Some("anna"), now).is_ok());
+        assert_eq!(limiter.metrics().failed_logins, 0);
+    }
+
+    #[test]
+    fn forgets_failures() {
+        let mut limiter = limiter();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+        for user in ["anna", "bert"] {
+            assert!(limiter.check(ip, Some(user), start).is_ok());
+            limiter.record_failure();
+        }
+        assert_eq!(limiter.users.len(), 2);
+        assert!(limiter.check(ip, None, start + secs(14 * 60)).is_ok());
+        assert_eq!(limiter.users.len(), 2);
+        assert!(limiter.check(ip, None, start + secs(15 * 60)).is_ok());
+        assert!(limiter.users.is_empty());
+    }
+}
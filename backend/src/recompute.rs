@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serde_derive::Serialize;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ScoreChange {
+    pub username: String,
+    pub day: i32,
+    pub old_score: f64,
+    pub new_score: f64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StandingChange {
+    pub username: String,
+    pub old_points: f64,
+    pub new_points: f64,
+    pub old_rank: usize,
+    pub new_rank: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct RecomputeReport {
+    // Set when the new scores were stored, None for a dry run
+    pub version: Option<i64>,
+    pub changed_scores: Vec<ScoreChange>,
+    pub standings: Vec<StandingChange>,
+}
+
+// 1-based rank of every user, users with the same points share a rank
+pub fn ranks(points: &HashMap<String, f64>) -> HashMap<String, usize> {
+    let mut sorted: Vec<f64> = points.values().cloned().collect();
+    sorted.sort_by(|a, b| b.total_cmp(a));
+    points
+        .iter()
+        .map(|(username, p)| {
+            let better = sorted.iter().take_while(|other| *other > p).count();
+            (username.clone(), better + 1)
+        })
+        .collect()
+}
+
+// Standing of every user before and after, ordered by the new rank
+pub fn standings(old: &HashMap<String, f64>, new: &HashMap<String, f64>) -> Vec<StandingChange> {
+    let old_ranks = ranks(old);
+    let new_ranks = ranks(new);
+    let mut standings: Vec<StandingChange> = new
+        .iter()
+        .map(|(username, new_points)| StandingChange {
+            username: username.clone(),
+            old_points: *old.get(username).unwrap_or(&0.0),
+            new_points: *new_points,
+            old_rank: *old_ranks.get(username).unwrap_or(&old_ranks.len()),
+            new_rank: new_ranks[username],
+        })
+        .collect();
+    standings.sort_by(|a, b| {
+        a.new_rank
+            .cmp(&b.new_rank)
+            .then_with(|| a.username.cmp(&b.username))
+    });
+    standings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(points: &[(&str, f64)]) -> HashMap<String, f64> {
+        points.iter().map(|(u, p)| (u.to_string(), *p)).collect()
+    }
+
+    #[test]
+    fn ranked() {
+        let cases = vec![
+            (vec![], vec![]),
+            (vec![("anna", 3.0)], vec![("anna", 1)]),
+            (
+                vec![("anna", 3.0), ("bert", 5.0), ("carl", 1.0)],
+                vec![("anna", 2), ("bert", 1), ("carl", 3)],
+            ),
+            // Ties share the rank and the next one is skipped
+            (
+                vec![("anna", 5.0), ("bert", 5.0), ("carl", 1.0)],
+                vec![("anna", 1), ("bert", 1), ("carl", 3)],
+            ),
+        ];
+        for (input, expected) in cases {
+            let expected: HashMap<String, usize> =
+                expected.iter().map(|(u, r)| (u.to_string(), *r)).collect();
+            assert_eq!(ranks(&points(&input)), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn standings_before_and_after() {
+        let old = points(&[("anna", 10.0), ("bert", 8.0)]);
+        let new = points(&[("anna", 6.0), ("bert", 8.0), ("carl", 8.0)]);
+        let summary: Vec<(String, f64, f64, usize, usize)> = standings(&old, &new)
+            .into_iter()
+            .map(|s| {
+                (
+                    s.username,
+                    s.old_points,
+                    s.new_points,
+                    s.old_rank,
+                    s.new_rank,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("bert".to_string(), 8.0, 8.0, 2, 1),
+                // Not ranked before, counted as last
+                ("carl".to_string(), 0.0, 8.0, 2, 1),
+                ("anna".to_string(), 10.0, 6.0, 1, 3),
+            ]
+        );
+    }
+}
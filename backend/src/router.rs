@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use hyper::{Body, Method, Request, StatusCode};
+use percent_encoding::percent_decode_str;
+use uuid::Uuid;
+
+pub type Response = hyper::Response<Body>;
+pub type BoxFuture = Pin<Box<dyn Future<Output = Result<Response>> + Send>>;
+pub type Handler = Arc<dyn Fn(Context) -> BoxFuture + Send + Sync>;
+// Gets the request before the handler and decides if and how `next` is called
+pub type Middleware = Arc<dyn Fn(Context, Handler) -> BoxFuture + Send + Sync>;
+
+pub struct Context {
+    pub req: Request<Body>,
+    pub ip: IpAddr,
+    // Values of the `:name` segments of the matched route
+    pub params: HashMap<String, String>,
+    // Set by the auth middleware
+    pub user: Option<Uuid>,
+}
+
+impl Context {
+    pub fn new(req: Request<Body>, ip: IpAddr) -> Self {
+        Self {
+            req,
+            ip,
+            params: HashMap::new(),
+            user: None,
+        }
+    }
+
+    pub fn user(&self) -> Result<Uuid> {
+        self.user.ok_or_else(|| {
+            anyhow::anyhow!(
+                "No user for {}, the route needs auth",
+                self.req.uri().path()
+            )
+        })
+    }
+
+    pub fn param(&self, name: &str) -> Result<&str> {
+        self.params
+            .get(name)
+            .map(|p| p.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Route has no parameter {}", name))
+    }
+
+    pub fn into_body(self) -> Body {
+        self.req.into_body()
+    }
+}
+
+pub fn handler<F, Fut>(f: F) -> Handler
+where
+    F: Fn(Context) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    Arc::new(move |ctx| Box::pin(f(ctx)))
+}
+
+pub fn middleware<F, Fut>(f: F) -> Middleware
+where
+    F: Fn(Context, Handler) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
+{
+    Arc::new(move |ctx, next| Box::pin(f(ctx, next)))
+}
+
+// The first middleware is the outermost and sees the request first
+pub fn wrap(handler: Handler, middleware: &[Middleware]) -> Handler {
+    middleware.iter().rev().fold(handler, |next, m| {
+        let m = m.clone();
+        Arc::new(move |ctx| m(ctx, next.clone()))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+struct Route {
+    segments: Vec<Segment>,
+    methods: Vec<(Method, Handler)>,
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .trim_matches('/')
+        .split('/')
+        .map(|s| match s.strip_prefix(':') {
+            Some(name) => Segment::Param(name.to_string()),
+            None => Segment::Literal(s.to_string()),
+        })
+        .collect()
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Param(name) if !part.is_empty() => {
+                    let value = percent_decode_str(part).decode_utf8().ok()?;
+                    params.insert(name.clone(), value.into_owned());
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    fn allowed(&self) -> String {
+        self.methods
+            .iter()
+            .map(|(m, _)| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub fn not_found(path: &str) -> Result<Response> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(format!("Unknown path: {}", path).into())?)
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    // Gets the requests no route matches
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `path` segments starting with ':' match any value, e.g. `/api/users/:id`
+    pub fn route(self, method: Method, path: &str, handler: Handler) -> Self {
+        self.route_segments(parse_pattern(path), method, handler)
+    }
+
+    // Runs the middleware for every route added so far, e.g. to require auth for a group of routes
+    pub fn layer(mut self, middleware: Middleware) -> Self {
+        for route in &mut self.routes {
+            for (_, handler) in &mut route.methods {
+                *handler = wrap(handler.clone(), std::slice::from_ref(&middleware));
+            }
+        }
+        self
+    }
+
+    pub fn fallback(mut self, handler: Handler) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    pub fn merge(mut self, other: Router) -> Self {
+        self.fallback = self.fallback.or(other.fallback);
+        for route in other.routes {
+            for (method, handler) in route.methods {
+                self = self.route_segments(route.segments.clone(), method, handler);
+            }
+        }
+        self
+    }
+
+    fn route_segments(mut self, segments: Vec<Segment>, method: Method, handler: Handler) -> Self {
+        match self.routes.iter_mut().find(|r| r.segments == segments) {
+            Some(route) => {
+                route.methods.retain(|(m, _)| *m != method);
+                route.methods.push((method, handler));
+            }
+            None => self.routes.push(Route {
+                segments,
+                methods: vec![(method, handler)],
+            }),
+        }
+        self
+    }
+
+    pub async fn dispatch(&self, mut ctx: Context) -> Result<Response> {
+        let path = ctx.req.uri().path().to_string();
+        let Some((route, params)) = self
+            .routes
+            .iter()
+            .find_map(|r| r.matches(&path).map(|params| (r, params)))
+        else {
+            return match &self.fallback {
+                Some(fallback) => fallback(ctx).await,
+                None => not_found(&path),
+            };
+        };
+        let Some((_, handler)) = route.methods.iter().find(|(m, _)| m == ctx.req.method()) else {
+            return Ok(hyper::Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(hyper::header::ALLOW, route.allowed())
+                .body(format!("Method {} not allowed for {}", ctx.req.method(), path).into())?);
+        };
+        ctx.params = params;
+        handler(ctx).await
+    }
+
+    pub fn into_handler(self) -> Handler {
+        let router = Arc::new(self);
+        Arc::new(move |ctx| {
+            let router = router.clone();
+            Box::pin(async move { router.dispatch(ctx).await })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(text: &'static str) -> Handler {
+        handler(move |ctx: Context| async move {
+            let params: Vec<String> = ["id", "day"]
+                .iter()
+                .filter_map(|p| ctx.param(p).ok().map(|v| format!("{}={}", p, v)))
+                .collect();
+            Ok(Response::new(
+                format!("{} {}", text, params.join(" ")).into(),
+            ))
+        })
+    }
+
+    // Prefixes the body of the response to show the order middleware runs in
+    fn tag(name: &'static str) -> Middleware {
+        middleware(move |ctx: Context, next: Handler| async move {
+            let res = next(ctx).await?;
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            Ok(Response::new(
+                format!("{}({})", name, String::from_utf8_lossy(&body)).into(),
+            ))
+        })
+    }
+
+    async fn call(router: &Router, method: Method, path: &str) -> (StatusCode, String, String) {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        let res = router
+            .dispatch(Context::new(req, "127.0.0.1".parse().unwrap()))
+            .await
+            .unwrap();
+        let allow = res
+            .headers()
+            .get(hyper::header::ALLOW)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8_lossy(&body).to_string(), allow)
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let router = Router::new()
+            .route(Method::GET, "/api/users", reply("list"))
+            .route(Method::GET, "/api/users/:id", reply("get"))
+            .route(Method::PUT, "/api/users/:id", reply("put"))
+            .route(Method::GET, "/api/users/:id/days/:day", reply("day"))
+            .route(Method::GET, "/api/users/me", reply("me"));
+        let cases = vec![
+            (Method::GET, "/api/users", StatusCode::OK, "list ", ""),
+            (Method::GET, "/api/users/", StatusCode::OK, "list ", ""),
+            (
+                Method::GET,
+                "/api/users/42",
+                StatusCode::OK,
+                "get id=42",
+                "",
+            ),
+            (
+                Method::PUT,
+                "/api/users/a%20b",
+                StatusCode::OK,
+                "put id=a b",
+                "",
+            ),
+            // Earlier routes win
+            (
+                Method::GET,
+                "/api/users/me",
+                StatusCode::OK,
+                "get id=me",
+                "",
+            ),
+            (
+                Method::GET,
+                "/api/users/7/days/3",
+                StatusCode::OK,
+                "day id=7 day=3",
+                "",
+            ),
+            (
+                Method::DELETE,
+                "/api/users/42",
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Method DELETE not allowed for /api/users/42",
+                "GET, PUT",
+            ),
+            (
+                Method::GET,
+                "/api/users/7/days",
+                StatusCode::NOT_FOUND,
+                "Unknown path: /api/users/7/days",
+                "",
+            ),
+            (
+                Method::GET,
+                "/api/other",
+                StatusCode::NOT_FOUND,
+                "Unknown path: /api/other",
+                "",
+            ),
+        ];
+        for (method, path, status, body, allow) in cases {
+            assert_eq!(
+                call(&router, method.clone(), path).await,
+                (status, body.to_string(), allow.to_string()),
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn layers() {
+        let router = Router::new()
+            .route(Method::GET, "/inner", reply("inner"))
+            .layer(tag("a"))
+            .layer(tag("b"))
+            .merge(Router::new().route(Method::GET, "/outer", reply("outer")));
+        assert_eq!(call(&router, Method::GET, "/inner").await.1, "b(a(inner ))");
+        assert_eq!(call(&router, Method::GET, "/outer").await.1, "outer ");
+
+        let app = wrap(router.into_handler(), &[tag("x"), tag("y")]);
+        let req = Request::get("/missing").body(Body::empty()).unwrap();
+        let res = app(Context::new(req, "127.0.0.1".parse().unwrap()))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "x(y(Unknown path: /missing))");
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db::Activity;
+use crate::doors::BonusRule;
+use crate::validation::ClientError;
+
+pub const MAX_SCORE: f64 = 10.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind")]
+pub enum LatePolicy {
+    // Late activities score the same as on time ones
+    NoPenalty,
+    // Full score when at most `days` late, otherwise the score is scaled by `factor`
+    Grace { days: i32, factor: f64 },
+    // Every day late removes `per_day` of the score, but never goes below `min`
+    LinearDecay { per_day: f64, min: f64 },
+    // Activities more than `days` late are rejected, others are scaled by `factor`
+    Cutoff { days: i32, factor: f64 },
+}
+
+impl Default for LatePolicy {
+    fn default() -> Self {
+        LatePolicy::Grace {
+            days: 0,
+            factor: 0.5,
+        }
+    }
+}
+
+impl LatePolicy {
+    // Factors are from 0 to 1, so late activities never score more than on time or below 0
+    pub fn check(&self) -> Result<()> {
+        let unit = |name: &str, value: f64| {
+            if (0.0..=1.0).contains(&value) {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "late_policy.{} must be from 0 to 1, not {}",
+                    name,
+                    value
+                ))
+            }
+        };
+        let at_least_zero = |name: &str, value: f64| {
+            if value >= 0.0 {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "late_policy.{} must be at least 0, not {}",
+                    name,
+                    value
+                ))
+            }
+        };
+        match self {
+            LatePolicy::NoPenalty => Ok(()),
+            LatePolicy::Grace { days, factor } | LatePolicy::Cutoff { days, factor } => {
+                at_least_zero("days", *days as f64)?;
+                unit("factor", *factor)
+            }
+            LatePolicy::LinearDecay { per_day, min } => {
+                at_least_zero("per_day", *per_day)?;
+                unit("min", *min)
+            }
+        }
+    }
+
+    // Factor to multiply the score with for an activity logged `days_late` days after its day
+    pub fn factor(&self, days_late: i32) -> Result<f64> {
+        if days_late <= 0 {
+            return Ok(1.0);
+        }
+        match self {
+            LatePolicy::NoPenalty => Ok(1.0),
+            LatePolicy::Grace { days, factor } => {
+                if days_late <= *days {
+                    Ok(1.0)
+                } else {
+                    Ok(*factor)
+                }
+            }
+            LatePolicy::LinearDecay { per_day, min } => {
+                Ok((1.0 - per_day * days_late as f64).max(*min))
+            }
+            LatePolicy::Cutoff { days, factor } => {
+                if days_late > *days {
+                    Err(ClientError::forbidden(&format!(
+                        "Too late, activities can only be logged {} days afterwards",
+                        days
+                    )))
+                } else {
+                    Ok(*factor)
+                }
+            }
+        }
+    }
+}
+
+// What the leaderboard is ranked by
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RankingMode {
+    // Activity scores only
+    #[default]
+    Activities,
+    // Activity scores plus achievement points
+    Combined,
+}
+
+impl RankingMode {
+    pub fn total(&self, activity_points: f64, achievement_points: f64) -> f64 {
+        match self {
+            RankingMode::Activities => activity_points,
+            RankingMode::Combined => activity_points + achievement_points,
+        }
+    }
+}
+
+pub fn multiplier(act: Activity) -> i32 {
+    match act {
+        Activity::BIKE => 3,
+        Activity::RUN => 1,
+        Activity::SKI => 2,
+        Activity::WALK => 1,
+        Activity::CLIMB => 20,
+    }
+}
+
+// Score of an activity on time, before any bonus, cap or late penalty
+fn base_score(activity: Activity, distance: f64, event_distance: i32) -> f64 {
+    10.0 * (distance / multiplier(activity) as f64) / event_distance as f64
+}
+
+// Final score of an activity given its raw inputs and the rules in use
+pub fn activity_score(
+    activity: Activity,
+    distance: f64,
+    event_distance: i32,
+    bonus: Option<&BonusRule>,
+    days_late: i32,
+    late_policy: &LatePolicy,
+) -> Result<f64> {
+    let mut score = base_score(activity, distance, event_distance);
+    if let Some(bonus) = bonus {
+        score = bonus.apply(activity, score);
+    }
+    // Capped after the bonus, so a door bonus can not lift a score above the maximum
+    Ok(score.min(MAX_SCORE) * late_policy.factor(days_late)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capped_after_bonus() {
+        let policy = LatePolicy::NoPenalty;
+        let score = |distance: f64, bonus: Option<&BonusRule>| {
+            activity_score(Activity::RUN, distance, 5, bonus, 0, &policy).unwrap()
+        };
+        assert_eq!(score(2.5, None), 5.0);
+        assert_eq!(score(10.0, None), MAX_SCORE);
+        assert_eq!(score(2.5, Some(&BonusRule::Flat(2.0))), 7.0);
+        assert_eq!(score(5.0, Some(&BonusRule::Flat(2.0))), MAX_SCORE);
+        assert_eq!(
+            score(
+                4.0,
+                Some(&BonusRule::ActivityMultiplier(Activity::RUN, 2.0))
+            ),
+            MAX_SCORE
+        );
+        // The cap comes before the late penalty
+        let late = activity_score(
+            Activity::RUN,
+            10.0,
+            5,
+            Some(&BonusRule::Flat(2.0)),
+            1,
+            &LatePolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(late, MAX_SCORE / 2.0);
+    }
+
+    #[test]
+    fn late_policies() {
+        let grace = LatePolicy::Grace {
+            days: 2,
+            factor: 0.5,
+        };
+        let decay = LatePolicy::LinearDecay {
+            per_day: 0.25,
+            min: 0.1,
+        };
+        let cutoff = LatePolicy::Cutoff {
+            days: 3,
+            factor: 0.0,
+        };
+        let default = LatePolicy::default();
+        let cases: Vec<(&str, &LatePolicy, i32, Option<f64>)> = vec![
+            ("no penalty on time", &LatePolicy::NoPenalty, 0, Some(1.0)),
+            ("no penalty late", &LatePolicy::NoPenalty, 20, Some(1.0)),
+            ("grace on time", &grace, 0, Some(1.0)),
+            ("within grace", &grace, 2, Some(1.0)),
+            ("after grace is half", &grace, 3, Some(0.5)),
+            ("long after grace is half", &grace, 20, Some(0.5)),
+            ("default is half when late", &default, 1, Some(0.5)),
+            ("decay on time", &decay, 0, Some(1.0)),
+            ("decay one day", &decay, 1, Some(0.75)),
+            ("decay three days", &decay, 3, Some(0.25)),
+            ("decay reaches min", &decay, 4, Some(0.1)),
+            ("decay beyond window", &decay, 20, Some(0.1)),
+            ("cutoff on time", &cutoff, 0, Some(1.0)),
+            ("before cutoff is zero", &cutoff, 1, Some(0.0)),
+            ("at cutoff is zero", &cutoff, 3, Some(0.0)),
+            ("after cutoff is rejected", &cutoff, 4, None),
+        ];
+        for (name, policy, days_late, expected) in cases {
+            assert_eq!(policy.factor(days_late).ok(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn checks_late_policies() {
+        let cases = vec![
+            (LatePolicy::NoPenalty, true),
+            (LatePolicy::default(), true),
+            (
+                LatePolicy::Grace {
+                    days: 2,
+                    factor: 1.5,
+                },
+                false,
+            ),
+            (
+                LatePolicy::Grace {
+                    days: -1,
+                    factor: 0.5,
+                },
+                false,
+            ),
+            (
+                LatePolicy::Cutoff {
+                    days: 3,
+                    factor: 0.0,
+                },
+                true,
+            ),
+            (
+                LatePolicy::Cutoff {
+                    days: 3,
+                    factor: -0.5,
+                },
+                false,
+            ),
+            (
+                LatePolicy::LinearDecay {
+                    per_day: 0.1,
+                    min: 0.2,
+                },
+                true,
+            ),
+            (
+                LatePolicy::LinearDecay {
+                    per_day: -0.1,
+                    min: 0.2,
+                },
+                false,
+            ),
+            (
+                LatePolicy::LinearDecay {
+                    per_day: 0.1,
+                    min: 1.2,
+                },
+                false,
+            ),
+        ];
+        for (policy, valid) in cases {
+            assert_eq!(policy.check().is_ok(), valid, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn ranking_modes() {
+        let cases = vec![
+            (RankingMode::Activities, 12.5, 3.0, 12.5),
+            (RankingMode::Activities, 0.0, 3.0, 0.0),
+            (RankingMode::Combined, 12.5, 3.0, 15.5),
+            (RankingMode::Combined, 0.0, 3.0, 3.0),
+        ];
+        for (mode, activities, achievements, expected) in cases {
+            assert_eq!(mode.total(activities, achievements), expected, "{:?}", mode);
+        }
+        assert_eq!(RankingMode::default(), RankingMode::Activities);
+        assert_eq!(
+            serde_json::from_str::<RankingMode>(r#""Combined""#).unwrap(),
+            RankingMode::Combined
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, StatusCode};
+use percent_encoding::percent_decode_str;
+
+use crate::router::{not_found, Context, Response};
+
+const INDEX: &str = "index.html";
+// Files under /static/ of a React build have a hash of their content in the name
+const IMMUTABLE_PREFIX: &str = "/static/";
+
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn cache_control(path: &str) -> &'static str {
+    if path.starts_with(IMMUTABLE_PREFIX) {
+        "public, max-age=31536000, immutable"
+    } else {
+        // Always checked with the ETag, so a new build is picked up right away
+        "no-cache"
+    }
+}
+
+// File of the request path within `root`, None for paths trying to leave it
+pub fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut file = root.to_path_buf();
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if segment == "." || segment == ".." || segment.contains('\\') {
+            return None;
+        }
+        file.push(segment);
+    }
+    Some(file)
+}
+
+// Paths of client side routes have no extension, missing files with one are not found
+fn is_app_route(path: &str) -> bool {
+    !path.rsplit('/').next().unwrap_or("").contains('.')
+}
+
+// Serves the built frontend in `root`, falling back to its index.html for the routes of the app
+pub async fn serve(root: &Path, ctx: Context) -> Result<Response> {
+    let path = ctx.req.uri().path().to_string();
+    if path.starts_with("/api/") {
+        return not_found(&path);
+    }
+    if !matches!(*ctx.req.method(), Method::GET | Method::HEAD) {
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "GET, HEAD")
+            .body(format!("Method {} not allowed for {}", ctx.req.method(), path).into())?);
+    }
+    let Some(mut file) = resolve(root, &path) else {
+        return not_found(&path);
+    };
+    let mut cache = cache_control(&path);
+    if tokio::fs::metadata(&file).await.is_ok_and(|m| m.is_dir()) {
+        file.push(INDEX);
+    }
+    let metadata = match tokio::fs::metadata(&file).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ if is_app_route(&path) => {
+            file = root.join(INDEX);
+            cache = cache_control(INDEX);
+            tokio::fs::metadata(&file).await?
+        }
+        _ => return not_found(&path),
+    };
+
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let etag = format!("\"{:x}-{:x}\"", metadata.len(), modified);
+    let builder = hyper::Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache);
+    let unchanged = ctx
+        .req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    if unchanged {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())?);
+    }
+    Ok(builder
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(content_type(&file)),
+        )
+        .body(tokio::fs::read(&file).await?.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    #[test]
+    fn resolves_within_root() {
+        let root = Path::new("/srv/build");
+        let cases = vec![
+            ("/", Some("/srv/build")),
+            ("/index.html", Some("/srv/build/index.html")),
+            (
+                "/static/js/main.1a2b.js",
+                Some("/srv/build/static/js/main.1a2b.js"),
+            ),
+            ("/a%20b.png", Some("/srv/build/a b.png")),
+            ("/../etc/passwd", None),
+            ("/static/%2e%2e/%2e%2e/etc/passwd", None),
+            ("/a\\..\\b", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(resolve(root, path), expected.map(PathBuf::from), "{}", path);
+        }
+    }
+
+    #[test]
+    fn content_types() {
+        let cases = vec![
+            ("index.html", "text/html; charset=utf-8"),
+            ("main.1a2b.JS", "text/javascript; charset=utf-8"),
+            ("main.css", "text/css; charset=utf-8"),
+            ("manifest.json", "application/json"),
+            ("logo192.png", "image/png"),
+            ("favicon.ico", "image/x-icon"),
+            ("LICENSE", "application/octet-stream"),
+        ];
+        for (file, expected) in cases {
+            assert_eq!(content_type(Path::new(file)), expected, "{}", file);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_build() {
+        let root = std::env::temp_dir().join(format!("build-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("static/js")).unwrap();
+        std::fs::write(root.join(INDEX), "<html></html>").unwrap();
+        std::fs::write(root.join("static/js/main.1a2b.js"), "let gah;").unwrap();
+
+        let get = |path: &str, etag: Option<&str>| {
+            let mut req = Request::get(path);
+            if let Some(etag) = etag {
+                req = req.header(header::IF_NONE_MATCH, etag);
+            }
+            Context::new(
+                req.body(Body::empty()).unwrap(),
+                "127.0.0.1".parse().unwrap(),
+            )
+        };
+        // Path, status, content type, cache control, body
+        let cases = vec![
+            (
+                "/",
+                StatusCode::OK,
+                "text/html",
+                "no-cache",
+                "<html></html>",
+            ),
+            (
+                "/leaderboard",
+                StatusCode::OK,
+                "text/html",
+                "no-cache",
+                "<html></html>",
+            ),
+            (
+                "/static/js/main.1a2b.js",
+                StatusCode::OK,
+                "text/javascript",
+                "immutable",
+                "let gah;",
+            ),
+            (
+                "/static/js/gone.js",
+                StatusCode::NOT_FOUND,
+                "",
+                "",
+                "Unknown path: /static/js/gone.js",
+            ),
+            (
+                "/api/nope",
+                StatusCode::NOT_FOUND,
+                "",
+                "",
+                "Unknown path: /api/nope",
+            ),
+        ];
+        for (path, status, content_type, cache, body) in cases {
+            let res = serve(&root, get(path, None)).await.unwrap();
+            let header = |name| {
+                res.headers()
+                    .get(name)
+                    .map(|v: &HeaderValue| v.to_str().unwrap().to_string())
+                    .unwrap_or_default()
+            };
+            assert_eq!(res.status(), status, "{}", path);
+            assert!(
+                header(header::CONTENT_TYPE).starts_with(content_type),
+                "{}",
+                path
+            );
+            assert!(header(header::CACHE_CONTROL).contains(cache), "{}", path);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(bytes, body, "{}", path);
+        }
+
+        let res = serve(&root, get("/", None)).await.unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let res = serve(&root, get("/", Some(&etag))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = serve(&root, get("/", Some("\"other\""))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
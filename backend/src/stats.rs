@@ -0,0 +1,166 @@
+use std::collections::BTreeSet;
+
+use serde_derive::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::db::Activity;
+use crate::evaluator::{ActivityEntry, Streaks};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredEntry {
+    pub entry: ActivityEntry,
+    pub score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ActivityTotals {
+    pub activity: Activity,
+    pub count: i32,
+    // Distance, or minutes for climbing
+    pub total: f64,
+    pub average: f64,
+    pub score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DayScore {
+    pub day: i32,
+    pub activity: Activity,
+    pub score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct UserStats {
+    pub current_streak: i32,
+    pub longest_streak: i32,
+    pub per_activity: Vec<ActivityTotals>,
+    pub best_day: Option<DayScore>,
+    // Score of every day so far, 0 for days without activity
+    pub score_per_day: Vec<f64>,
+    pub active_day_percentage: f64,
+    pub points: f64,
+    pub median_points: f64,
+    pub rank: usize,
+    // Points needed to reach the next rank, None when already first
+    pub points_to_next_rank: Option<f64>,
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+// Rank among all participants and the points missing to the closest participant above
+pub fn standing(points: f64, all_points: &[f64]) -> (usize, Option<f64>) {
+    let above: Vec<f64> = all_points.iter().cloned().filter(|p| *p > points).collect();
+    let next = above.iter().cloned().reduce(f64::min);
+    (above.len() + 1, next.map(|next| next - points))
+}
+
+// `elapsed_days` is the number of days of the challenge up to and including today
+pub fn user_stats(
+    activities: &[ScoredEntry],
+    elapsed_days: i32,
+    streaks: Streaks,
+    points: f64,
+    all_points: &[f64],
+) -> UserStats {
+    let per_activity = Activity::iter()
+        .map(|activity| {
+            let mut totals = ActivityTotals {
+                activity,
+                count: 0,
+                total: 0.0,
+                average: 0.0,
+                score: 0.0,
+            };
+            for a in activities.iter().filter(|a| a.entry.activity == activity) {
+                totals.count += 1;
+                totals.total += a.entry.distance;
+                totals.score += a.score;
+            }
+            if totals.count > 0 {
+                totals.average = totals.total / totals.count as f64;
+            }
+            totals
+        })
+        .collect();
+
+    let best_day = activities
+        .iter()
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+        .map(|a| DayScore {
+            day: a.entry.day,
+            activity: a.entry.activity,
+            score: a.score,
+        });
+
+    let mut score_per_day = vec![0.0; elapsed_days.max(0) as usize];
+    for a in activities {
+        if let Some(score) = score_per_day.get_mut(a.entry.day as usize) {
+            *score += a.score;
+        }
+    }
+
+    let active_days: BTreeSet<i32> = activities
+        .iter()
+        .map(|a| a.entry.day)
+        .filter(|day| *day < elapsed_days)
+        .collect();
+    let active_day_percentage = if score_per_day.is_empty() {
+        0.0
+    } else {
+        100.0 * active_days.len() as f64 / score_per_day.len() as f64
+    };
+
+    let (rank, points_to_next_rank) = standing(points, all_points);
+
+    UserStats {
+        current_streak: streaks.current,
+        longest_streak: streaks.longest,
+        per_activity,
+        best_day,
+        score_per_day,
+        active_day_percentage,
+        points,
+        median_points: median(all_points),
+        rank,
+        points_to_next_rank,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn medians() {
+        let cases: Vec<(Vec<f64>, f64)> = vec![
+            (vec![], 0.0),
+            (vec![3.0], 3.0),
+            (vec![5.0, 1.0, 3.0], 3.0),
+            (vec![4.0, 1.0, 3.0, 2.0], 2.5),
+        ];
+        for (values, expected) in cases {
+            assert_eq!(median(&values), expected, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn standings() {
+        let all = vec![10.0, 25.0, 17.5, 17.5, 3.0];
+        assert_eq!(standing(25.0, &all), (1, None));
+        assert_eq!(standing(17.5, &all), (2, Some(7.5)));
+        assert_eq!(standing(10.0, &all), (4, Some(7.5)));
+        assert_eq!(standing(3.0, &all), (5, Some(7.0)));
+    }
+}
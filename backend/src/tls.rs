@@ -0,0 +1,247 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::StatusCode;
+use native_tls::Identity;
+use serde_derive::Deserialize;
+use tokio::net::TcpListener;
+use tokio_native_tls::TlsAcceptor;
+
+use crate::router::{handler, Context, Handler};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    // PEM certificate chain, leaf first
+    pub cert_path: String,
+    // PEM private key in PKCS#8 form (`BEGIN PRIVATE KEY`)
+    pub key_path: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    // Keep listening for plain HTTP, only to redirect to HTTPS
+    #[serde(default = "default_redirect_http")]
+    pub redirect_http: bool,
+    // Port browsers reach HTTPS on when it is forwarded to `port`, e.g. 443
+    #[serde(default)]
+    pub public_port: Option<u16>,
+    // Host name put in redirects instead of the Host header sent by the client
+    #[serde(default)]
+    pub public_host: Option<String>,
+}
+
+// Clients that have not finished the handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Pause after a failed accept, which is mostly running out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+fn default_port() -> u16 {
+    8443
+}
+
+fn default_redirect_http() -> bool {
+    true
+}
+
+// Read again on SIGHUP, so renewed certificates are used without a restart
+pub type SharedAcceptor = Arc<RwLock<TlsAcceptor>>;
+
+pub fn load_acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    let cert = std::fs::read(&config.cert_path)
+        .map_err(|e| anyhow::anyhow!("Reading {} failed: {}", config.cert_path, e))?;
+    let key = std::fs::read(&config.key_path)
+        .map_err(|e| anyhow::anyhow!("Reading {} failed: {}", config.key_path, e))?;
+    let identity = Identity::from_pkcs8(&cert, &key)?;
+    Ok(native_tls::TlsAcceptor::new(identity)?.into())
+}
+
+#[cfg(unix)]
+pub async fn reload_on_hangup(config: TlsConfig, acceptor: SharedAcceptor) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            println!(
+                "Certificates will not be reloaded, listening for SIGHUP failed: {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match load_acceptor(&config) {
+            Ok(reloaded) => {
+                *acceptor.write().unwrap() = reloaded;
+                println!("Reloaded the certificate from {}", config.cert_path);
+            }
+            Err(e) => println!("Keeping the old certificate, reloading failed: {:?}", e),
+        }
+    }
+}
+
+pub async fn serve(addr: SocketAddr, acceptor: SharedAcceptor, app: Handler) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Listening on https://{}", addr);
+    loop {
+        let (tcp, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                println!("Accepting a connection failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.read().unwrap().clone();
+        let app = app.clone();
+        // The handshake is done apart from the accept loop, so a slow client can not hold it up
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    println!("TLS handshake with {} failed: {}", remote.ip(), e);
+                    return;
+                }
+                Err(_) => {
+                    println!("TLS handshake with {} timed out", remote.ip());
+                    return;
+                }
+            };
+            let service = service_fn(move |req| app(Context::new(req, remote.ip())));
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                println!("Connection with {} failed: {}", remote.ip(), e);
+            }
+        });
+    }
+}
+
+// Same host and path over HTTPS, `host` is the configured public host or the Host header
+pub fn redirect_location(host: &str, path_and_query: &str, port: u16) -> Option<String> {
+    let authority: hyper::http::uri::Authority = host.parse().ok()?;
+    let name = authority.host();
+    Some(match port {
+        443 => format!("https://{}{}", name, path_and_query),
+        _ => format!("https://{}:{}{}", name, port, path_and_query),
+    })
+}
+
+pub fn redirect(config: &TlsConfig) -> Handler {
+    let port = config.public_port.unwrap_or(config.port);
+    let public_host = config.public_host.clone();
+    handler(move |ctx: Context| {
+        let public_host = public_host.clone();
+        async move {
+            let host = match &public_host {
+                Some(host) => host.as_str(),
+                None => ctx
+                    .req
+                    .headers()
+                    .get(hyper::header::HOST)
+                    .and_then(|h| h.to_str().ok())
+                    .unwrap_or(""),
+            };
+            let path = ctx
+                .req
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or("/");
+            let Some(location) = redirect_location(host, path, port) else {
+                return Ok(hyper::Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("Missing or invalid Host header".into())?);
+            };
+            // Unlike 301, 308 keeps the method and body of the request
+            Ok(hyper::Response::builder()
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(hyper::header::LOCATION, location)
+                .body("Use HTTPS".into())?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(cert: &str, key: &str) -> TlsConfig {
+        TlsConfig {
+            cert_path: format!("testdata/{}", cert),
+            key_path: format!("testdata/{}", key),
+            port: default_port(),
+            redirect_http: true,
+            public_port: None,
+            public_host: None,
+        }
+    }
+
+    #[test]
+    fn loads_certificates() {
+        assert!(load_acceptor(&config("localhost-cert.pem", "localhost-key.pem")).is_ok());
+        // Files mixed up
+        assert!(load_acceptor(&config("localhost-key.pem", "localhost-cert.pem")).is_err());
+        assert!(load_acceptor(&config("missing.pem", "localhost-key.pem")).is_err());
+    }
+
+    #[test]
+    fn redirect_locations() {
+        let cases = vec![
+            (
+                "gah.example.com",
+                "/",
+                443,
+                Some("https://gah.example.com/"),
+            ),
+            (
+                "gah.example.com:8080",
+                "/api/today?x=1",
+                8443,
+                Some("https://gah.example.com:8443/api/today?x=1"),
+            ),
+            ("[::1]:8080", "/", 8443, Some("https://[::1]:8443/")),
+            ("", "/", 443, None),
+            ("gah.example.com/evil", "/", 443, None),
+        ];
+        for (host, path, port, expected) in cases {
+            assert_eq!(
+                redirect_location(host, path, port).as_deref(),
+                expected,
+                "{} {}",
+                host,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_to_public_host() {
+        let request = || {
+            Context::new(
+                hyper::Request::builder()
+                    .uri("/api/today")
+                    .header(hyper::header::HOST, "evil.example.com")
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+                "127.0.0.1".parse().unwrap(),
+            )
+        };
+        let location = |res: hyper::Response<hyper::Body>| {
+            res.headers()[hyper::header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let mut public = config("localhost-cert.pem", "localhost-key.pem");
+        let res = redirect(&public)(request()).await.unwrap();
+        assert_eq!(location(res), "https://evil.example.com:8443/api/today");
+
+        public.public_host = Some("gah.example.com".to_string());
+        public.public_port = Some(443);
+        let res = redirect(&public)(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(location(res), "https://gah.example.com/api/today");
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+
+use chrono_tz::Tz;
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_derive::Serialize;
+
+use crate::db::{Activity, CHALLENGE_DAYS};
+use crate::mail::parse_address;
+use crate::moderation::ActivityLimits;
+
+pub const USERNAME_MIN_CHARS: usize = 3;
+pub const USERNAME_MAX_CHARS: usize = 32;
+pub const PASSWORD_MAX_CHARS: usize = 128;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// Returned as a 400 response listing every invalid field of the request
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ValidationError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .errors
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "Invalid input, {}", fields.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationError {
+    pub fn field(field: &str, message: &str) -> anyhow::Error {
+        ValidationError {
+            errors: vec![FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            }],
+        }
+        .into()
+    }
+}
+
+// Request that can not be done for a reason other than invalid input, answered with the status
+// and the reason as json
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ClientError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub reason: String,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl ClientError {
+    fn with_status(status: StatusCode, reason: &str) -> anyhow::Error {
+        ClientError {
+            status,
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+
+    pub fn bad_request(reason: &str) -> anyhow::Error {
+        Self::with_status(StatusCode::BAD_REQUEST, reason)
+    }
+
+    // Wrong credentials
+    pub fn unauthorized(reason: &str) -> anyhow::Error {
+        Self::with_status(StatusCode::UNAUTHORIZED, reason)
+    }
+
+    // Not allowed for this user or not at this time
+    pub fn forbidden(reason: &str) -> anyhow::Error {
+        Self::with_status(StatusCode::FORBIDDEN, reason)
+    }
+
+    pub fn not_found(reason: &str) -> anyhow::Error {
+        Self::with_status(StatusCode::NOT_FOUND, reason)
+    }
+
+    // Clashes with what is already stored
+    pub fn conflict(reason: &str) -> anyhow::Error {
+        Self::with_status(StatusCode::CONFLICT, reason)
+    }
+}
+
+// Collects the errors of all fields so they can be reported at once
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(mut self, field: &str, result: Result<(), String>) -> Self {
+        if let Err(message) = result {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                message,
+            });
+        }
+        self
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                errors: self.errors,
+            }
+            .into())
+        }
+    }
+}
+
+// Request bodies that are not valid json or miss fields are reported as an error of the body
+pub fn parse_json<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    serde_json::from_slice(bytes).map_err(|e| ValidationError::field("body", &e.to_string()))
+}
+
+pub fn username(name: &str) -> Result<(), String> {
+    let chars = name.chars().count();
+    if !(USERNAME_MIN_CHARS..=USERNAME_MAX_CHARS).contains(&chars) {
+        return Err(format!(
+            "Must be {} to {} characters",
+            USERNAME_MIN_CHARS, USERNAME_MAX_CHARS
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || "_-.".contains(c))
+    {
+        return Err("May only contain letters, digits, '_', '-' and '.'".to_string());
+    }
+    Ok(())
+}
+
+pub fn password(pass: &str) -> Result<(), String> {
+    if pass.is_empty() {
+        return Err("Must not be empty".to_string());
+    }
+    if pass.chars().count() > PASSWORD_MAX_CHARS {
+        return Err(format!("Must be at most {} characters", PASSWORD_MAX_CHARS));
+    }
+    Ok(())
+}
+
+pub fn timezone(name: &str) -> Result<(), String> {
+    name.parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| format!("Unknown time zone: {}", name))
+}
+
+pub fn email(address: &str) -> Result<(), String> {
+    parse_address(address)
+        .map(|_| ())
+        .map_err(|_| "Not a valid email address".to_string())
+}
+
+pub fn day(day: i32) -> Result<(), String> {
+    if !(0..CHALLENGE_DAYS).contains(&day) {
+        return Err(format!("Must be between 0 and {}", CHALLENGE_DAYS - 1));
+    }
+    Ok(())
+}
+
+// `max` holds the largest value that can be logged for one day
+pub fn activity_value(activity: Activity, value: f64, max: &ActivityLimits) -> Result<(), String> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err("Must be a positive number".to_string());
+    }
+    if value > max.get(activity) {
+        return Err(format!(
+            "Must be at most {} for {:?}",
+            max.get(activity),
+            activity
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moderation::PlausibilityConfig;
+
+    #[test]
+    fn usernames() {
+        let cases = vec![
+            ("anna", true),
+            ("Åsa-Britt_2.0", true),
+            ("an", false),
+            ("", false),
+            (" anna", false),
+            ("anna svensson", false),
+            ("anna🎄", false),
+            ("a23456789012345678901234567890123", false),
+        ];
+        for (name, valid) in cases {
+            assert_eq!(username(name).is_ok(), valid, "{}", name);
+        }
+    }
+
+    #[test]
+    fn activity_values() {
+        let max = PlausibilityConfig::default().max;
+        let cases = vec![
+            (Activity::RUN, 5.0, true),
+            (Activity::RUN, 150.0, true),
+            (Activity::RUN, 150.1, false),
+            (Activity::CLIMB, 300.0, true),
+            (Activity::WALK, 0.0, false),
+            (Activity::WALK, -1.0, false),
+            (Activity::BIKE, f64::NAN, false),
+            (Activity::BIKE, f64::INFINITY, false),
+        ];
+        for (activity, value, valid) in cases {
+            assert_eq!(
+                activity_value(activity, value, &max).is_ok(),
+                valid,
+                "{:?} {}",
+                activity,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn collects_all_fields() {
+        let err = Validator::new()
+            .check("username", username("x"))
+            .check("pass", password("fine"))
+            .check("timezone", timezone("Mars/Olympus"))
+            .finish()
+            .unwrap_err();
+        let fields: Vec<String> = err
+            .downcast_ref::<ValidationError>()
+            .unwrap()
+            .errors
+            .iter()
+            .map(|e| e.field.clone())
+            .collect();
+        assert_eq!(fields, vec!["username", "timezone"]);
+        assert!(Validator::new()
+            .check("pass", password("ok"))
+            .finish()
+            .is_ok());
+    }
+}
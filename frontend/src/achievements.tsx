@@ -1,4 +1,4 @@
-import { Box, CircularProgress, Paper, Typography } from "@mui/material";
+import { Box, CircularProgress, LinearProgress, Paper, Typography } from "@mui/material";
 import React from "react";
 import { Achievement, Achievements, ServerAchievementsResponse } from "./protocol";
 import { GET } from "./transport";
@@ -41,30 +41,67 @@ export function AchievementsComponent() {
                 typeof data === 'undefined' ?
                     <br />
                     :
-                    data.achievements.map(a => {
+                    renderClosestToUnlocking(data.achievements)
+            }
+            {
+                typeof data === 'undefined' ?
+                    <br />
+                    :
+                    data.achievements.map((a, index) => {
                         if (a.unlocked) {
                             unlockedNumCounter++;
                         }
-                        return renderAchievement(a, unlockedNumCounter);
+                        return renderAchievement(a, unlockedNumCounter, index);
                     })
             }
         </div>
     )
 }
 
-export function renderAchievement(achievement: Achievement, unlockedNum: number): React.ReactFragment {
+export function renderAchievement(achievement: Achievement, unlockedNum: number, index?: number): React.ReactFragment {
     console.log(achievement)
     return (
-        <div className={achievement.unlocked ? "achievement-unlocked" : "achievement-locked"} key={achievement.title} style={{ animationDelay: `${unlockedNum * 0.1}s` }}>
+        <div className={achievement.unlocked ? "achievement-unlocked" : "achievement-locked"} key={`${achievement.title}${index ?? ''}`} style={{ animationDelay: `${unlockedNum * 0.1}s` }}>
             <Paper elevation={10} >
                 <Typography variant="caption" component="div" >{renderTrophy(achievement.rank)}</Typography>
                 <Typography variant="caption" component="div" color="text.primary">{achievement.title}</Typography>
                 <Typography variant="caption" component="div" color="text.secondary">{achievement.description}</Typography>
+                {achievement.unlocked || achievement.secret ? undefined : renderProgress(achievement)}
             </Paper>
         </div>
     )
 }
 
+function progressPercent(achievement: Achievement): number {
+    return Math.min(100, achievement.progress * 100 / achievement.target);
+}
+
+function renderProgress(achievement: Achievement): React.ReactFragment {
+    return (
+        <div>
+            <LinearProgress variant="determinate" value={progressPercent(achievement)} />
+            <Typography variant="caption" component="div" color="text.secondary">
+                {`${Math.round(achievement.progress * 10) / 10}/${achievement.target}`}
+            </Typography>
+        </div>
+    )
+}
+
+function renderClosestToUnlocking(achievements: Achievement[]): React.ReactFragment {
+    const closest = achievements
+        .filter(a => !a.unlocked && !a.secret && a.progress > 0)
+        .sort((a, b) => progressPercent(b) - progressPercent(a))
+        .slice(0, 3);
+    if (closest.length === 0) {
+        return <br />;
+    }
+    return (
+        <Typography variant="caption" component="div" color="text.secondary">
+            {`Closest to unlocking: ${closest.map(a => `${a.title} (${Math.round(progressPercent(a))}%)`).join(', ')}`}
+        </Typography>
+    )
+}
+
 // Highly inspired from https://mui.com/components/progress/
 function CircularProgressWithLabel(props: { value: number, total: number, unlocked: number }) {
     return (
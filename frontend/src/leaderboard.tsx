@@ -1,16 +1,26 @@
-import { Paper, styled, Table, TableBody, TableContainer, TableHead, TableRow } from "@mui/material";
+import { Paper, styled, Table, TableBody, TableContainer, TableHead, TableRow, ToggleButton, ToggleButtonGroup } from "@mui/material";
 import TableCell, { tableCellClasses } from '@mui/material/TableCell';
 import React from "react";
 import { renderTrophy } from "./achievementRank";
-import { LeaderboardDetail, ServerLeaderboardResponse } from "./protocol";
+import { Activity, ClientLeaderboardRequest, LeaderboardDetail, ServerLeaderboardResponse } from "./protocol";
 import { GET } from "./transport";
 
 export function Leaderboard() {
     const [rows, setRows] = React.useState<LeaderboardDetail[]>([]);
+    const [activityFilter, setActivityFilter] = React.useState<Activity | 'ALL'>('ALL');
+    const [loadedFilter, setLoadedFilter] = React.useState<Activity | 'ALL' | undefined>(undefined);
+
+    const handleChangeFilter = (event: any, newFilter: Activity | 'ALL' | null) => {
+        if (newFilter !== null) {
+            setActivityFilter(newFilter);
+        }
+    }
 
     setTimeout(() => {
-        if (rows.length === 0) {
-            GET<ServerLeaderboardResponse>('/leaderboard')
+        if (loadedFilter !== activityFilter) {
+            setLoadedFilter(activityFilter);
+            const filter: ClientLeaderboardRequest = activityFilter === 'ALL' ? {} : { activity: activityFilter };
+            GET<ServerLeaderboardResponse>('/leaderboard', { ...filter })
                 .then((reply) => {
                     reply.details.sort((a, b) => {
                         if (a.points < b.points) {
@@ -29,6 +39,14 @@ export function Leaderboard() {
 
     return (
         <div>
+            <ToggleButtonGroup size="small" color="primary" value={activityFilter} exclusive onChange={handleChangeFilter} >
+                <ToggleButton value="ALL">All</ToggleButton>
+                <ToggleButton value="BIKE">Bike</ToggleButton>
+                <ToggleButton value="RUN">Run</ToggleButton>
+                <ToggleButton value="WALK">Walk</ToggleButton>
+                <ToggleButton value="SKI">Ski</ToggleButton>
+                <ToggleButton value="CLIMB">Climb</ToggleButton>
+            </ToggleButtonGroup>
             <TableContainer component={Paper}>
                 <Table>
                     <TableHead>
@@ -40,6 +58,7 @@ export function Leaderboard() {
                             <StyledTableCell align='right'>Walk</StyledTableCell>
                             <StyledTableCell align='right'>Ski</StyledTableCell>
                             <StyledTableCell align='right'>Climb</StyledTableCell>
+                            <StyledTableCell align='right'>Streak</StyledTableCell>
                             <StyledTableCell align='right'></StyledTableCell>
                             <StyledTableCell align='right'></StyledTableCell>
                             <StyledTableCell align='right'></StyledTableCell>
@@ -51,12 +70,13 @@ export function Leaderboard() {
                             rows.map(row => (
                                 <StyledTableRow key={row.username}>
                                     <StyledTableCell component='th' scope='row'>{row.username}</StyledTableCell>
-                                    <StyledTableCell>{Math.round(row.points * 100) / 100}</StyledTableCell>
+                                    <StyledTableCell title={`Activities: ${Math.round(row.breakdown.activities * 100) / 100}, achievements: ${row.breakdown.achievements}`}>{Math.round(row.points * 100) / 100}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.bike_dst}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.run_dst}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.walk_dst}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.ski_dst}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.climb_time}</StyledTableCell>
+                                    <StyledTableCell align='right'>{row.current_streak} ({row.longest_streak})</StyledTableCell>
                                     <StyledTableCell align='right'>{row.bronze_achievements} {renderTrophy('BRONZE')}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.silver_achievements} {renderTrophy('SILVER')}</StyledTableCell>
                                     <StyledTableCell align='right'>{row.gold_achievements} {renderTrophy('GOLD')}</StyledTableCell>
@@ -1,15 +1,15 @@
 
 
 // Used for PUT /register-user
-export type ClientRegisterUserRequest = UsernamePass;
+export type ClientRegisterUserRequest = UsernamePass & { timezone?: string, email?: string };
 export type ServerRegisterUserResponse = SessionKeyResponse;
 
 // Used for POST /login
 export type ClientLoginRequest = UsernamePass;
 export type ServerLoginResponse = SessionKeyResponse;
 
-// Used for /leaderboard
-// export type ClientLeaderboardRequest = Range;
+// Used for /leaderboard, sent as query parameters
+export type ClientLeaderboardRequest = LeaderboardFilter;
 export type ServerLeaderboardResponse = LeaderboardInfo;
 
 // Used for GET /calendar
@@ -23,8 +23,80 @@ export type ServerLogActivityResponse = Achievements;
 // Used for /achievements
 export type ServerAchievementsResponse = Achievements;
 
+// Used for PUT /timezone
+export interface ClientTimezoneRequest {
+    timezone: string;   // IANA name, e.g. "Europe/Stockholm"
+}
+
+// Used for GET /doors
+export type ServerDoorsResponse = Door[];
+
+// Used for GET /leaderboard/history
+export type ServerLeaderboardHistoryResponse = LeaderboardHistory;
+
+// Used for GET /me/stats
+export type ServerUserStatsResponse = UserStats;
+
+// Used for PUT /me/password, other sessions are logged out
+export interface ClientChangePasswordRequest {
+    old_pass: string;
+    new_pass: string;
+}
+export type ServerChangePasswordResponse = SessionKeyResponse;
+
+// Used for PUT /me/email, the address password reset tokens are mailed to
+export interface ClientEmailRequest {
+    email: string;
+}
+
+// Used for POST /password-reset, mails a token if the user has an email
+export interface ClientRequestPasswordResetRequest {
+    username: string;   // Username or email
+}
+
+// Used for PUT /password-reset
+export interface ClientPasswordResetRequest {
+    token: string;
+    pass: string;
+}
+export type ServerPasswordResetResponse = SessionKeyResponse;
+
+// Used for PUT /me/username
+export interface ClientRenameRequest {
+    username: string;
+}
+
+// Used for DELETE /me, removes the account and all its activities
+export interface ClientDeleteAccountRequest {
+    pass: string;
+}
+
+// Used for GET /me/export, everything stored about the logged in user
+export interface ServerPersonalDataResponse {
+    schema_version: number;
+    exported_at: string;
+    user: { id: string, username: string, timezone: string };
+    email: string;
+    activities: { day: number, activity: Activity, distance: number, score: number, days_late: number }[];
+    achievements: { title: string, rank: AchievementRank }[];
+    stats: UserStats;
+    score_history: unknown[];
+}
+
+// Used for GET /admin/moderation, admins only
+export type ServerModerationQueueResponse = FlaggedActivity[];
+
+// Used for PUT /admin/moderation, answers with the remaining queue
+export type ClientModerationRequest = { user_id: string, day: number } & (
+    { action: 'Approve' } | { action: 'Reject' } | { action: 'Adjust', value: number });
+
+// Sent with status 400 by any route when the request has invalid fields
+export interface ServerValidationErrorResponse {
+    errors: { field: string, message: string }[];  // field is e.g. "username" or "info.value"
+}
+
 // Routes
-export type GetRoutes = '/calendar' | '/leaderboard' | '/achievements';
+export type GetRoutes = '/calendar' | '/leaderboard' | '/achievements' | '/doors' | '/me/stats' | '/leaderboard/history' | '/me/export';
 
 
 // Type definitions below
@@ -38,9 +110,15 @@ interface SessionKeyResponse {
     session_key: string;
 }
 
+export interface PointsBreakdown {
+    activities: number;     // Sum of activity scores
+    achievements: number;   // Points from unlocked achievements
+}
+
 export interface LeaderboardDetail {
     username: string;
-    points: number;
+    points: number;     // Total the leaderboard is ranked by
+    breakdown: PointsBreakdown;
     bike_dst: number;   // Total bike distance
     run_dst: number;    // Total run distance
     walk_dst: number;   // Total walk distance
@@ -50,6 +128,8 @@ export interface LeaderboardDetail {
     silver_achievements: number;
     gold_achievements: number;
     diamond_achievements: number;
+    current_streak: number;     // Active days in a row up to today
+    longest_streak: number;
 }
 
 interface LeaderboardInfo {
@@ -58,6 +138,32 @@ interface LeaderboardInfo {
     details: LeaderboardDetail[];
 }
 
+export interface UserHistory {
+    username: string;
+    ranks: Array<number | null>;    // One per day in LeaderboardHistory.days, null before the user joined
+    points: Array<number | null>;
+}
+
+export interface Climber {
+    day: number;
+    username: string;
+    from_rank: number;
+    to_rank: number;
+}
+
+interface LeaderboardHistory {
+    days: number[];         // Days with a snapshot taken at the end of the day
+    users: UserHistory[];
+    climbers: Climber[];    // Biggest climber of each day
+}
+
+export interface LeaderboardFilter {
+    activity?: Activity;
+    day?: number;   // Single day, overrides from and to
+    from?: number;  // First day, included
+    to?: number;    // Last day, included
+}
+
 interface Range {
     start: number;
     end?: number;
@@ -73,6 +179,19 @@ export interface ActivityInfo {
 export interface LoggedActivityInfo {
     day: number;    // day of dec 0-23
     info: ActivityInfo;
+    status?: ModerationStatus;  // Flagged and Rejected activities do not count
+}
+
+export type ModerationStatus = 'Ok' | 'Flagged' | 'Approved' | 'Adjusted' | 'Rejected';
+
+export interface FlaggedActivity {
+    user_id: string;
+    username: string;
+    day: number;
+    activity: Activity;
+    distance: number;
+    score: number;
+    reason: string;
 }
 
 interface CalendarGetRequest {
@@ -85,6 +204,50 @@ interface CalendarResponse {
     logged_activities?: LoggedActivityInfo[];
 }
 
+export type BonusRule =
+    | { ActivityMultiplier: [Activity, number] }
+    | { Flat: number };
+
+export interface DoorContent {
+    target: string;
+    message: string;
+    image_url?: string;
+    bonus?: BonusRule;
+}
+
+export interface Door {
+    day: number;    // day of dec 0-23, only opened doors are ever sent
+    content: DoorContent;
+    activities: ActivityInfo[];
+}
+
+export interface ActivityTotals {
+    activity: Activity;
+    count: number;
+    total: number;      // Distance, or minutes for climbing
+    average: number;
+    score: number;
+}
+
+export interface DayScore {
+    day: number;
+    activity: Activity;
+    score: number;
+}
+
+export interface UserStats {
+    current_streak: number;
+    longest_streak: number;
+    per_activity: ActivityTotals[];
+    best_day?: DayScore;
+    score_per_day: number[];        // One entry per day so far, 0 for days without activity
+    active_day_percentage: number;
+    points: number;
+    median_points: number;
+    rank: number;
+    points_to_next_rank?: number;   // Missing when first on the leaderboard
+}
+
 export type AchievementRank = 'BRONZE' | 'SILVER' | 'GOLD' | 'DIAMOND';
 
 export interface Achievement {
@@ -92,6 +255,9 @@ export interface Achievement {
     description: string;
     unlocked: boolean;
     rank: AchievementRank;
+    progress: number;   // e.g. runs, km or days so far
+    target: number;     // progress needed to unlock
+    secret: boolean;    // title and description are masked until unlocked
 }
 
 export interface Achievements {
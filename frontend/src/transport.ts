@@ -3,7 +3,10 @@ import { ActivityInfo, LoggedActivityInfo, ServerCalendarResponse, GetRoutes } f
 import { getUserState } from './user';
 // Implementation of network protocol here
 
-const SERVER_BASE_URL = 'http://localhost:3000/api';
+// Same origin as the page by default, which is the development proxy or the backend serving the
+// build. Set REACT_APP_SERVER_URL when the backend is on another origin, it has to allow this one
+// in `cors`
+export const SERVER_BASE_URL = (process.env.REACT_APP_SERVER_URL ?? '') + '/api';
 
 
 export function getLoggedActivityInfo(day: number, loggedActivities: LoggedActivityInfo[]): ActivityInfo | undefined {
@@ -25,11 +28,11 @@ export async function getCalendarInfo(): Promise<ServerCalendarResponse> {
 }
 
 
-export async function GET<T>(route: GetRoutes): Promise<T> {
+export async function GET<T>(route: GetRoutes, query?: Record<string, string | number | undefined>): Promise<T> {
     return new Promise<T>(async (resolve, reject) => {
         const extra_headers = getExtraHeaders();
         try {
-            const response = await fetch(SERVER_BASE_URL + route, { headers: { ...extra_headers } });
+            const response = await fetch(SERVER_BASE_URL + route + toQueryString(query), { headers: { ...extra_headers } });
 
             const data = await response.json() as T;
             resolve(data);
@@ -85,6 +88,20 @@ export async function PUT<T>(route: string, body: string): Promise<T> {
 }
 
 
+function toQueryString(query?: Record<string, string | number | undefined>): string {
+    if (typeof query === 'undefined') {
+        return '';
+    }
+    const params = new URLSearchParams();
+    for (const [key, value] of Object.entries(query)) {
+        if (typeof value !== 'undefined') {
+            params.append(key, value.toString());
+        }
+    }
+    const str = params.toString();
+    return str.length === 0 ? '' : '?' + str;
+}
+
 function getExtraHeaders(): any {
-    return typeof getUserState().session_key === 'undefined' ? {} : { 'Authentification': getUserState().session_key };
+    return typeof getUserState().session_key === 'undefined' ? {} : { 'Authorization': 'Bearer ' + getUserState().session_key };
 }
@@ -1,7 +1,7 @@
 import { Button, Dialog, DialogActions, DialogContent, DialogTitle, TextField } from "@mui/material";
 import React from "react";
-import { ClientLoginRequest, ServerLoginResponse, ServerRegisterUserResponse } from "./protocol";
-import { POST, PUT } from "./transport";
+import { ClientRegisterUserRequest, ServerLoginResponse, ServerRegisterUserResponse } from "./protocol";
+import { POST, PUT, SERVER_BASE_URL } from "./transport";
 import './user.css';
 import { Md5 } from 'ts-md5/dist/md5';
 
@@ -21,6 +21,28 @@ function storeUserState() {
     localStorage.setItem('user_state', JSON.stringify(userState));
 }
 
+// After single sign-on the server sends the browser back with the session or the error in the
+// fragment
+function takeSingleSignOn() {
+    const params = new URLSearchParams(window.location.hash.substring(1));
+    const error = params.get('error');
+    if (error !== null) {
+        window.history.replaceState(null, '', window.location.pathname + window.location.search);
+        alert(`Failed to login: ${error}`);
+        return;
+    }
+    const session_key = params.get('session_key');
+    if (session_key === null) {
+        return;
+    }
+    userState.session_key = session_key;
+    userState.username = params.get('username') ?? "";
+    storeUserState();
+    window.history.replaceState(null, '', window.location.pathname + window.location.search);
+}
+
+takeSingleSignOn();
+
 export function isUserLoggedIn(): boolean {
     return typeof getUserState().session_key === 'string';
 }
@@ -60,6 +82,7 @@ export function UserBar() {
                     <div>
                         <Button onClick={openLoginDialog}>Login</Button>
                         <Button onClick={openRegisterDialog}>Register</Button>
+                        <Button href={SERVER_BASE_URL + '/oidc/login'}>Login with SSO</Button>
                     </div>
             }
             {LoginDialog(loginDialogOpen, isLoginKind, setLoginDialogOpen, setIsLoggedIn, setWelcomeDialogOpen)}
@@ -108,9 +131,10 @@ function LoginDialog(loginDialogOpen: boolean, isLoginKind: boolean,
         setLoginDialogOpen(false);
         if (currentTargetId === 'login' || currentTargetId === 'register') {
             // Login with server
-            const req: ClientLoginRequest = {
+            const req: ClientRegisterUserRequest = {
                 username: username,
                 pass: Md5.hashStr(password),
+                timezone: Intl.DateTimeFormat().resolvedOptions().timeZone,
             };
             try {
                 let reply;